@@ -0,0 +1,253 @@
+//! Turns a program into a `loop { match pc { .. } }` state machine. Each entry
+//! point gets an arm holding straight-line code up to the next jump or I/O.
+//!
+//! Any failure (a negative address, an unknown instruction, a jump somewhere
+//! that wasn't compiled) returns `None` with `pc` left on the instruction, so
+//! the interpreter takes over and reports it exactly as it would have. Writes
+//! into compiled code also hand over, once the write has happened, except for
+//! operands the program patches through a fixed address (the usual way of
+//! indexing arrays), which are read from memory instead of compiled in.
+
+use crate::decode::{find_code, Instruction, Operand};
+
+use std::path::Path;
+
+const RUNTIME: &str = "::aoc2019::intcode::compiled";
+const RESULT: &str = "::aoc2019::intcode::CpuResult";
+
+struct Generator<'a> {
+    code: &'a [Option<Instruction>],
+    /// Addresses with their own arm
+    entries: &'a [bool],
+    /// Words which hand over to the interpreter when written
+    compiled: Vec<bool>,
+    /// Operands read from memory at runtime
+    patched: Vec<bool>,
+    out: String,
+}
+
+pub fn generate(path: &Path, memory: &[i64]) -> String {
+    let (code, entries) = find_code(memory);
+
+    let mut compiled = vec![false; memory.len()];
+    let mut operands = vec![false; memory.len()];
+    let mut targets = vec![false; memory.len()];
+    for (addr, instr) in code.iter().enumerate() {
+        if let Some(instr) = instr {
+            compiled[addr] = true;
+            for word in addr + 1..(addr + instr.size()).min(memory.len()) {
+                compiled[word] = true;
+                operands[word] = true;
+            }
+            if let (true, Some(&Operand::Position(dest))) = (instr.stores(), instr.args.last()) {
+                if dest >= 0 && (dest as usize) < memory.len() {
+                    targets[dest as usize] = true;
+                }
+            }
+        }
+    }
+    let patched: Vec<_> = (0..memory.len())
+        .map(|word| operands[word] && targets[word] && code[word].is_none())
+        .collect();
+    for (word, &patched) in patched.iter().enumerate() {
+        compiled[word] &= !patched;
+    }
+
+    let mut gen = Generator {
+        code: &code,
+        entries: &entries,
+        compiled,
+        patched,
+        out: String::new(),
+    };
+
+    gen.line("{");
+    // Rebuilds the crate when the program changes
+    gen.line(&format!("const _: &[u8] = include_bytes!({:?});", path));
+    gen.line(&format!(
+        "static MEMORY: [i64; {}] = {:?};",
+        memory.len(),
+        memory
+    ));
+    gen.line(&format!(
+        "static CODE: [bool; {}] = {:?};",
+        gen.compiled.len(),
+        gen.compiled
+    ));
+
+    gen.line(&format!(
+        "fn run(m: &mut {}::Machine) -> Option<{}> {{",
+        RUNTIME, RESULT
+    ));
+    gen.line("loop { match m.pc {");
+    for (addr, _) in entries.iter().enumerate().filter(|&(_, &entry)| entry) {
+        if code[addr].is_some() {
+            gen.block(addr);
+        }
+    }
+    gen.line("_ => return None, } } }");
+
+    gen.line("#[allow(unused_variables)]");
+    gen.line(&format!(
+        "fn input(m: &mut {}::Machine, value: i64) -> Option<()> {{",
+        RUNTIME
+    ));
+    gen.line("match m.pc {");
+    for (addr, instr) in code.iter().enumerate() {
+        if let Some(Instruction { opcode: 3, args }) = instr {
+            gen.line(&format!("{} => {{", addr));
+            gen.store(addr + 1, args[0], "value");
+            gen.line(&format!("m.pc = {}; Some(()) }}", addr + 2));
+        }
+    }
+    gen.line("_ => None, } }");
+
+    gen.line(&format!(
+        "{}::Compiled::new(&MEMORY, &CODE, run, input)",
+        RUNTIME
+    ));
+    gen.line("}");
+    gen.out
+}
+
+impl Generator<'_> {
+    fn line(&mut self, line: &str) {
+        self.out.push_str(line);
+        self.out.push('\n');
+    }
+
+    /// Straight-line code for the arm starting at `start`
+    fn block(&mut self, start: usize) {
+        self.line(&format!("{} => {{", start));
+        let mut addr = start;
+        loop {
+            let instr = self.code[addr].as_ref().unwrap();
+            let next = addr + instr.size();
+            let args = &instr.args;
+            self.line(&format!("m.pc = {};", addr));
+
+            match instr.opcode {
+                1 | 2 | 7 | 8 => {
+                    let a = self.load(addr + 1, args[0]);
+                    let b = self.load(addr + 2, args[1]);
+                    let value = match instr.opcode {
+                        1 => format!("i64::wrapping_add({}, {})", a, b),
+                        2 => format!("i64::wrapping_mul({}, {})", a, b),
+                        7 => format!("({} < {}) as i64", a, b),
+                        _ => format!("({} == {}) as i64", a, b),
+                    };
+                    self.line(&format!("let value = {};", value));
+                    if self.store(addr + 3, args[2], "value") {
+                        self.line(&format!("if m.stale {{ m.pc = {}; return None; }}", next));
+                    }
+                }
+                3 => {
+                    self.line(&format!("return Some({}::Input);", RESULT));
+                    break;
+                }
+                4 => {
+                    let value = self.load(addr + 1, args[0]);
+                    self.line(&format!("let value = {};", value));
+                    self.line(&format!(
+                        "m.pc = {}; return Some({}::Output(value));",
+                        next, RESULT
+                    ));
+                    break;
+                }
+                5 | 6 => {
+                    let jump = self.jump(addr + 2, args[1]);
+                    match args[0] {
+                        Operand::Immediate(cond) if !self.is_patched(addr + 1) => {
+                            if (cond != 0) == (instr.opcode == 5) {
+                                self.line(&jump);
+                                break;
+                            }
+                        }
+                        cond => {
+                            let cond = self.load(addr + 1, cond);
+                            let test = if instr.opcode == 5 { "!=" } else { "==" };
+                            self.line(&format!("if {} {} 0 {{ {} }}", cond, test, jump));
+                        }
+                    }
+                }
+                9 => {
+                    let value = self.load(addr + 1, args[0]);
+                    self.line(&format!("m.rbo = m.rbo.wrapping_add({});", value));
+                }
+                _ => {
+                    self.line(&format!("return Some({}::Halt);", RESULT));
+                    break;
+                }
+            }
+
+            // Carry on in this arm unless the next instruction has its own
+            if next >= self.code.len() || self.code[next].is_none() || self.entries[next] {
+                self.line(&format!("m.pc = {};", next));
+                break;
+            }
+            addr = next;
+        }
+        self.line("}");
+    }
+
+    /// The raw value of the operand stored at `word`
+    fn value(&self, word: usize, arg: Operand) -> String {
+        match arg {
+            _ if self.is_patched(word) => format!("m.word({})", word),
+            Operand::Position(value) | Operand::Immediate(value) | Operand::Relative(value) => {
+                format!("({}i64)", value)
+            }
+        }
+    }
+
+    fn load(&self, word: usize, arg: Operand) -> String {
+        let value = self.value(word, arg);
+        match arg {
+            Operand::Immediate(_) => value,
+            Operand::Position(_) => format!("m.load({})?", value),
+            Operand::Relative(_) => format!("m.load_rel({})?", value),
+        }
+    }
+
+    /// Emits a write of `value`, returning whether it might have hit compiled code
+    fn store(&mut self, word: usize, arg: Operand, value: &str) -> bool {
+        match arg {
+            Operand::Position(addr)
+                if addr >= 0 && !self.is_patched(word) && !self.is_compiled(addr) =>
+            {
+                self.line(&format!("m.write({}, {})?;", addr, value));
+                false
+            }
+            Operand::Position(_) => {
+                let addr = self.value(word, arg);
+                self.line(&format!("m.store({}, {})?;", addr, value));
+                true
+            }
+            Operand::Relative(_) => {
+                let offset = self.value(word, arg);
+                self.line(&format!("m.store_rel({}, {})?;", offset, value));
+                true
+            }
+            Operand::Immediate(_) => unreachable!("destinations never decode as immediates"),
+        }
+    }
+
+    /// Operands can run off the end of the program, those are never patched
+    fn is_patched(&self, word: usize) -> bool {
+        self.patched.get(word) == Some(&true)
+    }
+
+    fn is_compiled(&self, addr: i64) -> bool {
+        self.compiled.get(addr as usize) == Some(&true)
+    }
+
+    fn jump(&self, word: usize, target: Operand) -> String {
+        match target {
+            Operand::Immediate(target) if !self.is_patched(word) => match target {
+                target if target < 0 => "return None;".into(),
+                target => format!("m.pc = {}; continue;", target),
+            },
+            target => format!("m.jump({})?; continue;", self.load(word, target)),
+        }
+    }
+}
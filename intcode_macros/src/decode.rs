@@ -0,0 +1,194 @@
+//! A copy of the interpreter's decoder, and a reachability analysis built on
+//! the disassembler's. The macro can't depend on `aoc2019` since that crate
+//! depends on the macro.
+//!
+//! Unlike the disassembler, `find_code` also follows pointers found in data.
+//! A computed jump to an address that wasn't compiled hands the rest of the
+//! run to the interpreter, so every entry point found here keeps the program
+//! in compiled code. The disassembler only has to print a readable listing,
+//! so it doesn't guess. The two are expected to disagree about what is code.
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Operand {
+    Position(i64),
+    Immediate(i64),
+    Relative(i64),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Instruction {
+    pub opcode: i64,
+    pub args: Vec<Operand>,
+}
+
+impl Instruction {
+    pub fn size(&self) -> usize {
+        self.args.len() + 1
+    }
+
+    /// Whether the last operand is written to
+    pub fn stores(&self) -> bool {
+        matches!(self.opcode, 1 | 2 | 3 | 7 | 8)
+    }
+}
+
+/// Decodes the instruction at `pc`, or `None` if the interpreter would reject it
+pub fn decode(memory: &[i64], pc: usize) -> Option<Instruction> {
+    let read = |addr: usize| memory.get(addr).copied().unwrap_or(0);
+    let instr = read(pc);
+    let opcode = instr % 100;
+    let count = match opcode {
+        1 | 2 | 7 | 8 => 3,
+        5 | 6 => 2,
+        3 | 4 | 9 => 1,
+        99 => 0,
+        _ => return None,
+    };
+
+    let mut instruction = Instruction {
+        opcode,
+        args: Vec::with_capacity(count),
+    };
+    let mut modes = instr / 100;
+    for arg in 1..=count {
+        let store = instruction.stores() && arg == count;
+        let value = read(pc + arg);
+        instruction.args.push(match (modes % 10) & !(store as i64) {
+            0 => Operand::Position(value),
+            1 => Operand::Immediate(value),
+            2 => Operand::Relative(value),
+            _ => return None,
+        });
+        modes /= 10;
+    }
+    Some(instruction)
+}
+
+/// Decodes every instruction reachable from address 0, following immediate
+/// jump targets and pushed return addresses. Returns the instructions by
+/// address and the addresses where execution can resume or be jumped to.
+///
+/// Computed jumps can also land on addresses kept in tables or right after
+/// a return, so those are tried too, as long as they don't disagree with the
+/// code already found.
+pub fn find_code(program: &[i64]) -> (Vec<Option<Instruction>>, Vec<bool>) {
+    let mut code = vec![None; program.len()];
+    let mut entries = vec![false; program.len()];
+    let mut guesses = Vec::new();
+    explore(program, 0, &mut code, &mut entries, &mut guesses);
+
+    let mut tried = vec![false; program.len()];
+    while let Some(guess) = guesses.pop() {
+        if guess >= program.len() || tried[guess] || code[guess].is_some() {
+            continue;
+        }
+        tried[guess] = true;
+
+        let mut new_code = code.clone();
+        let mut new_entries = entries.clone();
+        let mut new_guesses = Vec::new();
+        explore(
+            program,
+            guess,
+            &mut new_code,
+            &mut new_entries,
+            &mut new_guesses,
+        );
+        if overlaps(&code, &new_code) {
+            continue;
+        }
+        code = new_code;
+        entries = new_entries;
+        guesses.extend(new_guesses);
+    }
+
+    (code, entries)
+}
+
+fn explore(
+    program: &[i64],
+    start: usize,
+    code: &mut [Option<Instruction>],
+    entries: &mut [bool],
+    guesses: &mut Vec<usize>,
+) {
+    let mut pending = vec![(start, true)];
+    while let Some((addr, entry)) = pending.pop() {
+        if addr >= program.len() {
+            continue;
+        }
+        entries[addr] |= entry;
+        if code[addr].is_some() {
+            continue;
+        }
+        let instr = match decode(program, addr) {
+            Some(instr) => instr,
+            None => continue,
+        };
+
+        let next = addr + instr.size();
+        let target = |op| match op {
+            Operand::Immediate(target) if target >= 0 => Some((target as usize, true)),
+            _ => None,
+        };
+
+        match (instr.opcode, instr.args.as_slice()) {
+            (99, _) => (),
+            (5, &[Operand::Immediate(cond), dest]) | (6, &[Operand::Immediate(cond), dest])
+                if (cond != 0) == (instr.opcode == 5) =>
+            {
+                match target(dest) {
+                    Some(dest) => pending.push(dest),
+                    None => guesses.push(next),
+                }
+            }
+            (5, &[_, dest]) | (6, &[_, dest]) => {
+                pending.extend(target(dest));
+                pending.push((next, false));
+            }
+            // Execution picks up after I/O in a fresh call to `resume`
+            (3, _) | (4, _) => pending.push((next, true)),
+            _ => {
+                match constant(&instr) {
+                    Some((ret, Operand::Relative(_))) => pending.extend(target(ret)),
+                    Some((value, _)) => guesses.extend(target(value).map(|(addr, _)| addr)),
+                    None => (),
+                }
+                pending.push((next, false));
+            }
+        }
+        code[addr] = Some(instr);
+    }
+}
+
+/// Whether instructions added to `old` in `new` cover any word that was
+/// already part of an instruction
+fn overlaps(old: &[Option<Instruction>], new: &[Option<Instruction>]) -> bool {
+    let mut covered = vec![false; old.len()];
+    for (addr, instr) in old.iter().enumerate() {
+        if let Some(instr) = instr {
+            let end = (addr + instr.size()).min(old.len());
+            covered[addr..end].iter_mut().for_each(|word| *word = true);
+        }
+    }
+    (old.iter().zip(new).enumerate())
+        .filter(|(_, (old, new))| old.is_none() && new.is_some())
+        .any(|(addr, (_, new))| {
+            let end = (addr + new.as_ref().unwrap().size()).min(old.len());
+            covered[addr..end].iter().any(|&word| word)
+        })
+}
+
+/// Recognises `add #x, #0, dest` and `mul #x, #1, dest`, the usual ways of
+/// storing a constant. With a relative destination it's most likely pushing
+/// a return address before a call.
+fn constant(instr: &Instruction) -> Option<(Operand, Operand)> {
+    use Operand::Immediate;
+
+    let value = match (instr.opcode, instr.args.as_slice()) {
+        (1, &[Immediate(a), Immediate(b), _]) if a == 0 || b == 0 => a + b,
+        (2, &[Immediate(a), Immediate(b), _]) if a == 1 || b == 1 => a * b,
+        _ => return None,
+    };
+    Some((Immediate(value), instr.args[2]))
+}
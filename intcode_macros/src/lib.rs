@@ -0,0 +1,64 @@
+//! `intcode_fn!` compiles an Intcode program into Rust at build time.
+//!
+//! ```ignore
+//! let mut program = intcode_fn!("src/bin/input/day09.txt");
+//! let keycode = program.compute(1);
+//! ```
+//!
+//! The path is relative to the manifest directory of the crate using the
+//! macro. The expansion is an `aoc2019::intcode::compiled::Compiled`, which has
+//! the same `resume`/`input` contract as `Cpu`.
+
+extern crate proc_macro;
+
+use proc_macro::{Delimiter, TokenStream, TokenTree};
+
+use std::path::PathBuf;
+
+mod codegen;
+mod decode;
+
+#[proc_macro]
+pub fn intcode_fn(input: TokenStream) -> TokenStream {
+    let generated = path(input).and_then(|path| {
+        let text = std::fs::read_to_string(&path)
+            .map_err(|err| format!("couldn't read {}: {}", path.display(), err))?;
+        let memory = parse(&text)?;
+        Ok(codegen::generate(&path, &memory))
+    });
+    let code = match generated {
+        Ok(code) => code,
+        Err(msg) => format!("compile_error!({:?})", msg),
+    };
+    code.parse().expect("generated code should be valid tokens")
+}
+
+fn path(input: TokenStream) -> Result<PathBuf, String> {
+    let tokens: Vec<_> = input.into_iter().collect();
+    let literal = match tokens.as_slice() {
+        [TokenTree::Literal(literal)] => literal.to_string(),
+        // Literals passed through `macro_rules!` arrive wrapped in a group
+        [TokenTree::Group(group)] if group.delimiter() == Delimiter::None => {
+            return path(group.stream())
+        }
+        _ => return Err("expected the path of an Intcode program".into()),
+    };
+    let relative = match literal.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+        Some(path) if !path.contains('\\') => path.to_string(),
+        _ => return Err("expected a plain string literal".into()),
+    };
+
+    let root = std::env::var("CARGO_MANIFEST_DIR").map_err(|err| err.to_string())?;
+    Ok(PathBuf::from(root).join(relative))
+}
+
+fn parse(text: &str) -> Result<Vec<i64>, String> {
+    text.split(',')
+        .map(str::trim)
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            word.parse()
+                .map_err(|_| format!("invalid Intcode value `{}`", word))
+        })
+        .collect()
+}
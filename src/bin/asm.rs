@@ -0,0 +1,46 @@
+use aoc2019::intcode::asm::assemble;
+
+use std::io::Read;
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let path = match args.next() {
+        Some(path) => path,
+        None => {
+            eprintln!("Usage: asm <source.s | -> [output.txt]");
+            std::process::exit(1);
+        }
+    };
+
+    let mut source = String::new();
+    if path == "-" {
+        std::io::stdin().read_to_string(&mut source).expect("um");
+    } else {
+        source = std::fs::read_to_string(&path).expect("Couldn't read source");
+    }
+
+    let program = match assemble(&source) {
+        Ok(program) => program,
+        Err(err) => {
+            eprintln!("error: {}", err.message);
+            eprintln!(" --> {}:{}:{}", path, err.line, err.column);
+            if let Some(line) = source.lines().nth(err.line - 1) {
+                eprintln!("  |");
+                eprintln!("  | {}", line);
+                eprintln!("  | {:>width$}", "^", width = err.column);
+            }
+            std::process::exit(1);
+        }
+    };
+
+    let output = program
+        .iter()
+        .map(|w| w.to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+
+    match args.next() {
+        Some(out) => std::fs::write(out, output + "\n").expect("Couldn't write program"),
+        None => println!("{}", output),
+    }
+}
@@ -1,4 +1,4 @@
-use aoc2019::intcode::Cpu;
+use aoc2019::intcode::{Cpu, Exit};
 
 static INPUT: &str = include_str!("input/day02.txt");
 
@@ -10,7 +10,7 @@ fn process(noun: i64, verb: i64) -> i64 {
     let mut cpu = Cpu::new(opcodes());
     cpu.memory[1] = noun;
     cpu.memory[2] = verb;
-    cpu.run(());
+    assert_eq!(cpu.run(()), Exit::Halt);
     cpu.memory[0]
 }
 
@@ -53,3 +53,15 @@ fn part_2_benchmark_excl_parse(bench: &mut test::Bencher) {
         test::black_box(cpu.compute(PART_2_INPUT));
     });
 }
+
+#[cfg(test)]
+#[bench]
+fn part_2_benchmark_threaded(bench: &mut test::Bencher) {
+    use aoc2019::intcode::threaded::ThreadedCpu;
+
+    let memory = aoc2019::intcode::parse(INPUT);
+    bench.iter(|| {
+        let mut cpu = ThreadedCpu::new(memory.clone());
+        test::black_box(cpu.compute(PART_2_INPUT));
+    });
+}
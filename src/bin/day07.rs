@@ -1,4 +1,5 @@
-use aoc2019::intcode::{parse, Cpu, CpuResult};
+use aoc2019::intcode::circuit::Circuit;
+use aoc2019::intcode::parse;
 
 use once_cell::sync::Lazy;
 use permute::permutations_of;
@@ -7,32 +8,19 @@ static INPUT: &str = include_str!("input/day07.txt");
 static PROGRAM: Lazy<Vec<i64>> = Lazy::new(|| parse(INPUT));
 
 fn try_sequence<'a>(seq: impl Iterator<Item = &'a i64>) -> i64 {
-    let mut cpus = Vec::with_capacity(5);
-
-    // Create each CPU and input its phase
-    for &phase in seq {
-        let mut cpu = Cpu::new(PROGRAM.clone());
-        match cpu.resume() {
-            CpuResult::Input => cpu.input(phase),
-            _ => unreachable!(),
-        }
-        cpus.push(cpu);
+    // A ring of amplifiers, the first also getting the initial signal
+    let mut circuit = Circuit::new();
+    for (i, &phase) in seq.enumerate() {
+        let inputs = if i == 0 { vec![phase, 0] } else { vec![phase] };
+        circuit.add_node(&PROGRAM, &inputs);
     }
-
-    let mut value = 0;
-    let mut i = 0;
-    loop {
-        match cpus[i].resume() {
-            CpuResult::Halt => break,
-            CpuResult::Input => cpus[i].input(value),
-            CpuResult::Output(out) => {
-                value = out;
-                i = (i + 1) % cpus.len();
-            }
-        }
+    let len = circuit.len();
+    for i in 0..len {
+        circuit.connect(i, (i + 1) % len);
     }
 
-    value
+    circuit.run().unwrap();
+    *circuit.outputs(len - 1).last().unwrap()
 }
 
 fn find_max_sequence(phases: &[i64]) -> i64 {
@@ -1,3 +1,7 @@
+#![cfg_attr(test, feature(test))]
+#[cfg(test)]
+extern crate test;
+
 use aoc2019::intcode::Cpu;
 
 static INPUT: &str = include_str!("input/day09.txt");
@@ -10,3 +14,32 @@ fn main() {
     println!("Part 1: {}", compute_boost(1));
     println!("Part 2: {}", compute_boost(2));
 }
+
+#[cfg(test)]
+#[bench]
+fn part2_interpreted(bench: &mut test::Bencher) {
+    bench.iter(|| {
+        test::black_box(compute_boost(2));
+    });
+}
+
+#[cfg(test)]
+#[bench]
+fn part2_compiled(bench: &mut test::Bencher) {
+    use aoc2019::intcode::intcode_fn;
+
+    bench.iter(|| {
+        test::black_box(intcode_fn!("src/bin/input/day09.txt").compute(2));
+    });
+}
+
+#[cfg(all(test, feature = "jit"))]
+#[bench]
+fn part2_jit(bench: &mut test::Bencher) {
+    use aoc2019::intcode::jit::JitCpu;
+
+    let memory = aoc2019::intcode::parse(INPUT);
+    bench.iter(|| {
+        test::black_box(JitCpu::new(memory.clone()).compute(2));
+    });
+}
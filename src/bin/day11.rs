@@ -1,4 +1,4 @@
-use aoc2019::intcode::{Cpu, IO};
+use aoc2019::intcode::{Cpu, Exit, Flow, Input, IO};
 
 use std::collections::HashMap;
 use std::ops::{Add, Sub};
@@ -57,11 +57,11 @@ fn paint(hull: &mut HashMap<Vec2, i64>) {
     }
 
     impl IO for Painter<'_> {
-        fn input(&mut self) -> i64 {
-            color(self.pos, self.hull)
+        fn input(&mut self) -> Input {
+            Input::Value(color(self.pos, self.hull))
         }
 
-        fn output(&mut self, value: i64) {
+        fn output(&mut self, value: i64) -> Flow {
             self.state = match self.state {
                 State::Paint => {
                     self.hull.insert(self.pos, value);
@@ -72,16 +72,18 @@ fn paint(hull: &mut HashMap<Vec2, i64>) {
                     self.pos = self.pos + DIRS[self.dir];
                     State::Paint
                 }
-            }
+            };
+            Flow::Continue
         }
     }
 
-    Cpu::parse(INPUT).run(Painter {
+    let painter = Painter {
         hull,
         pos: Vec2::default(),
         dir: 0,
         state: State::Paint,
-    });
+    };
+    assert_eq!(Cpu::parse(INPUT).run(painter), Exit::Halt);
 }
 
 fn bounds(hull: &HashMap<Vec2, i64>) -> (i32, i32, i32, i32) {
@@ -2,7 +2,7 @@
 #[cfg(test)]
 extern crate test;
 
-use aoc2019::intcode::{parse, Cpu, IO};
+use aoc2019::intcode::{parse, Cpu, Exit, Flow, Input, IO};
 
 use std::cmp::Ordering::*;
 use std::time::Duration;
@@ -52,10 +52,10 @@ struct GameState {
 }
 
 impl IO for GameState {
-    fn output(&mut self, value: i64) {
+    fn output(&mut self, value: i64) -> Flow {
         if self.out_buf.len() < 2 {
             self.out_buf.push(value);
-            return;
+            return Flow::Continue;
         }
 
         let x = self.out_buf[0];
@@ -73,9 +73,10 @@ impl IO for GameState {
 
             self.screen.set(x as usize, y as usize, value as u8);
         }
+        Flow::Continue
     }
 
-    fn input(&mut self) -> i64 {
+    fn input(&mut self) -> Input {
         if self.draw {
             let start = std::time::Instant::now();
             print!("{}", term_cursor::Relative(0, -22));
@@ -86,11 +87,11 @@ impl IO for GameState {
             }
         }
 
-        match self.ball_x.cmp(&self.paddle_x) {
+        Input::Value(match self.ball_x.cmp(&self.paddle_x) {
             Less => -1,
             Equal => 0,
             Greater => 1,
-        }
+        })
     }
 }
 
@@ -117,11 +118,23 @@ impl GameState {
 fn plain_run() -> usize {
     let mut state = GameState::default();
 
-    Cpu::new(PROGRAM.clone()).run(&mut state);
+    assert_eq!(Cpu::new(PROGRAM.clone()).run(&mut state), Exit::Halt);
 
     state.screen.data.iter().filter(|&&v| v == 2).count()
 }
 
+#[cfg(test)]
+fn dynamic_run_threaded() -> i64 {
+    use aoc2019::intcode::threaded::ThreadedCpu;
+
+    let mut state = GameState::default();
+    let mut cpu = ThreadedCpu::new(PROGRAM.clone());
+    cpu.memory_mut()[0] = 2;
+    assert_eq!(cpu.run(&mut state), Exit::Halt);
+
+    state.score
+}
+
 fn dynamic_run(draw: bool, draw_duration: Duration) -> i64 {
     let mut state = GameState {
         draw,
@@ -135,7 +148,7 @@ fn dynamic_run(draw: bool, draw_duration: Duration) -> i64 {
 
     let mut cpu = Cpu::new(PROGRAM.clone());
     cpu.memory[0] = 2;
-    cpu.run(&mut state);
+    assert_eq!(cpu.run(&mut state), Exit::Halt);
 
     state.score
 }
@@ -171,3 +184,15 @@ fn part2(bench: &mut test::Bencher) {
         test::black_box(dynamic_run(false, Default::default()));
     });
 }
+
+#[cfg(test)]
+#[bench]
+fn part2_threaded(bench: &mut test::Bencher) {
+    assert_eq!(
+        dynamic_run_threaded(),
+        dynamic_run(false, Default::default())
+    );
+    bench.iter(|| {
+        test::black_box(dynamic_run_threaded());
+    });
+}
@@ -1,4 +1,4 @@
-use aoc2019::intcode::{Cpu, CpuResult::*};
+use aoc2019::intcode::{Cpu, Exit, Flow, Input, IO};
 
 use std::collections::HashMap;
 use std::ops::{Add, Sub};
@@ -151,19 +151,28 @@ impl State {
     }*/
 }
 
+/// Explores until there's nowhere left to go
+impl IO for State {
+    fn input(&mut self) -> Input {
+        if self.has_next() {
+            Input::Value(self.next_input())
+        } else {
+            Input::Stop
+        }
+    }
+
+    fn output(&mut self, value: i64) -> Flow {
+        self.update(value);
+        Flow::Continue
+    }
+}
+
 fn main() {
     let mut state = State::default();
     let mut cpu = Cpu::parse(INPUT);
 
     state.update(1);
-
-    while state.has_next() {
-        match cpu.resume() {
-            Halt => panic!(),
-            Input => cpu.input(state.next_input()),
-            Output(result) => state.update(result),
-        }
-    }
+    assert_eq!(cpu.run(&mut state), Exit::Stop);
 
     let p1 = state.path_length((0, 0).into(), state.end);
     println!("Part 1: {}", p1);
@@ -0,0 +1,22 @@
+use aoc2019::intcode::{disasm::disassemble, parse};
+
+use std::io::Read;
+
+fn main() {
+    let path = match std::env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("Usage: disasm <program.txt | ->");
+            std::process::exit(1);
+        }
+    };
+
+    let mut input = String::new();
+    if path == "-" {
+        std::io::stdin().read_to_string(&mut input).expect("um");
+    } else {
+        input = std::fs::read_to_string(&path).expect("Couldn't read program");
+    }
+
+    print!("{}", disassemble(&parse(&input)));
+}
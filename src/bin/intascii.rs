@@ -0,0 +1,397 @@
+use aoc2019::intcode::{parse, AsciiIO, Cpu, CpuResult, Input, IO};
+
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{BufRead, BufWriter, IsTerminal, Read, Write};
+use std::process::{Command, Stdio};
+
+static HELP: &str = "\
+Anything not starting with `:` or `!` is sent to the program. In a terminal
+Up and Down go through the history, and Left, Right, Home, End, Backspace,
+Delete, Ctrl-A, Ctrl-E and Ctrl-U edit the line.
+Commands:
+  !!                     send the last line again
+  !<n>                   send line n from the history
+  :history               list the lines sent so far
+  :save <name>           remember the cpu and its output as they are now
+  :load <name>           go back to a remembered cpu, showing its output
+  :saves                 list remembered cpus
+  :save-file <path>      write a save state of the cpu and its output
+  :load-file <path>      replace the cpu with a save state
+  :help                  show this
+  :quit                  exit";
+
+/// Prints to stdout, copying everything to the transcript if there is one
+struct Console {
+    log: Option<BufWriter<File>>,
+}
+
+impl Console {
+    fn print(&mut self, text: &str) {
+        print!("{}", text);
+        std::io::stdout().flush().expect("um");
+        if let Some(log) = &mut self.log {
+            log.write_all(text.as_bytes())
+                .expect("Couldn't write transcript");
+            log.flush().expect("Couldn't write transcript");
+        }
+    }
+}
+
+/// What to do after a line is entered
+enum Action {
+    Send(String),
+    /// Carry on running a newly loaded cpu
+    Resume,
+    Prompt,
+    Quit,
+}
+
+struct Session {
+    cpu: Cpu,
+    io: AsciiIO,
+    console: Console,
+    /// Lines still to come from the script, before reading from stdin
+    script: VecDeque<String>,
+    history: Vec<String>,
+    /// Output since the last line was sent, kept with saves to show again
+    screen: String,
+    /// Cpu save states and the screen at the time
+    saves: HashMap<String, (Vec<u8>, String)>,
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let usage = || -> ! {
+        eprintln!("Usage: intascii <program.txt> [--script <path>] [--log <path>]");
+        std::process::exit(1);
+    };
+    let path = args.next().unwrap_or_else(|| usage());
+    let mut script = VecDeque::new();
+    let mut log = None;
+    while let Some(flag) = args.next() {
+        let value = args.next().unwrap_or_else(|| usage());
+        match flag.as_str() {
+            "--script" => {
+                let text = std::fs::read_to_string(&value).expect("Couldn't read script");
+                script = text.lines().map(str::to_string).collect();
+            }
+            "--log" => {
+                let file = File::create(&value).expect("Couldn't create transcript");
+                log = Some(BufWriter::new(file));
+            }
+            _ => usage(),
+        }
+    }
+
+    let program = std::fs::read_to_string(&path).expect("Couldn't read program");
+    let mut session = Session {
+        cpu: Cpu::new(parse(&program)),
+        io: AsciiIO::new(),
+        console: Console { log },
+        script,
+        history: Vec::new(),
+        screen: String::new(),
+        saves: HashMap::new(),
+    };
+    session.run();
+}
+
+impl Session {
+    fn run(&mut self) {
+        loop {
+            match self.cpu.try_resume() {
+                Ok(CpuResult::Output(value)) => {
+                    self.io.output(value);
+                    self.flush_output();
+                }
+                Ok(CpuResult::Input) => match self.io.input() {
+                    Input::Value(input) => {
+                        if let Err(err) = self.cpu.try_input(input) {
+                            self.console.print(&format!("\n❌ {}\n", err));
+                            break;
+                        }
+                    }
+                    Input::Pause | Input::Stop => {
+                        if !self.prompt() {
+                            break;
+                        }
+                    }
+                },
+                Ok(CpuResult::Halt) => {
+                    let text = self.io.take_text();
+                    self.console.print(&text);
+                    self.console.print("\nProgram halted\n");
+                    break;
+                }
+                Err(err) => {
+                    self.console.print(&format!("\n❌ {}\n", err));
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Prints finished lines, and anything that isn't ASCII on its own line
+    fn flush_output(&mut self) {
+        for line in self.io.lines.drain(..) {
+            self.console.print(&line);
+            self.console.print("\n");
+            self.screen += &line;
+            self.screen.push('\n');
+        }
+        for value in self.io.values.drain(..) {
+            let value = format!("{}\n", value);
+            self.console.print(&value);
+            self.screen += &value;
+        }
+    }
+
+    /// Reads lines until one goes to the program or the cpu is replaced,
+    /// returning false to exit
+    fn prompt(&mut self) -> bool {
+        let text = self.io.take_text();
+        self.console.print(&text);
+        self.screen += &text;
+        loop {
+            // Whatever is left of the last line, to show again after commands
+            let prompt = self.screen.rsplit('\n').next().unwrap_or("").to_string();
+            let line = match self.script.pop_front() {
+                Some(line) => {
+                    self.console.print(&format!("{}\n", line));
+                    line
+                }
+                None => {
+                    let line = match read_line(&prompt, &self.history) {
+                        Some(line) => line,
+                        None => return false,
+                    };
+                    // Typed lines are already on screen
+                    if let Some(log) = &mut self.console.log {
+                        writeln!(log, "{}", line).expect("Couldn't write transcript");
+                    }
+                    line
+                }
+            };
+
+            match self.command(&line) {
+                Ok(Action::Send(input)) => {
+                    self.io.push_line(&input);
+                    self.history.push(input);
+                    self.screen.clear();
+                    return true;
+                }
+                Ok(Action::Resume) => return true,
+                Ok(Action::Quit) => return false,
+                Ok(Action::Prompt) => (),
+                Err(msg) => self.console.print(&format!("❌ {}\n", msg)),
+            }
+            self.console.print(&prompt);
+        }
+    }
+
+    fn command(&mut self, line: &str) -> Result<Action, String> {
+        if let Some(rest) = line.strip_prefix('!') {
+            let entry = match rest {
+                "!" => self.history.last(),
+                n => {
+                    let n: usize = n.parse().map_err(|_| format!("Invalid number `{}`", n))?;
+                    n.checked_sub(1).and_then(|i| self.history.get(i))
+                }
+            };
+            let entry = entry.ok_or("No such line in the history")?.clone();
+            self.console.print(&format!("{}\n", entry));
+            return Ok(Action::Send(entry));
+        }
+        if !line.starts_with(':') {
+            return Ok(Action::Send(line.to_string()));
+        }
+
+        let words: Vec<&str> = line[1..].split_whitespace().collect();
+        let arg = || words.get(1).copied().ok_or("Expected an argument");
+        match words.first().copied().unwrap_or("") {
+            "help" => self.console.print(&format!("{}\n", HELP)),
+            "quit" => return Ok(Action::Quit),
+            "history" => {
+                let listing: String = (self.history.iter().enumerate())
+                    .map(|(i, line)| format!("{:>4}  {}\n", i + 1, line))
+                    .collect();
+                self.console.print(&listing);
+            }
+            "save" => {
+                let mut state = Vec::new();
+                self.cpu.save(&mut state).map_err(|e| e.to_string())?;
+                let save = (state, self.screen.clone());
+                self.saves.insert(arg()?.to_string(), save);
+            }
+            "load" => {
+                let (state, screen) = self.saves.get(arg()?).ok_or("No save with that name")?;
+                let cpu = Cpu::load(&state[..]).map_err(|e| e.to_string())?;
+                let screen = screen.clone();
+                return Ok(self.restore(cpu, screen));
+            }
+            "saves" => {
+                let mut names: Vec<_> = self.saves.keys().cloned().collect();
+                names.sort();
+                self.console.print(&format!("{}\n", names.join(" ")));
+            }
+            "save-file" => {
+                let mut file = File::create(arg()?).map_err(|e| e.to_string())?;
+                self.cpu.save(&mut file).map_err(|e| e.to_string())?;
+                // The cpu reads exactly its own state back, so the text can follow
+                file.write_all(self.screen.as_bytes())
+                    .map_err(|e| e.to_string())?;
+            }
+            "load-file" => {
+                let mut file = File::open(arg()?).map_err(|e| e.to_string())?;
+                let cpu = Cpu::load(&mut file).map_err(|e| e.to_string())?;
+                let mut screen = String::new();
+                file.read_to_string(&mut screen)
+                    .map_err(|e| e.to_string())?;
+                return Ok(self.restore(cpu, screen));
+            }
+            cmd => return Err(format!("Unknown command `:{}`, try `:help`", cmd)),
+        }
+        Ok(Action::Prompt)
+    }
+
+    /// Swaps in a loaded cpu, showing what was on screen when it was saved
+    fn restore(&mut self, cpu: Cpu, screen: String) -> Action {
+        self.cpu = cpu;
+        self.io = AsciiIO::new();
+        self.console.print(&screen);
+        self.screen = screen;
+        Action::Resume
+    }
+}
+
+/// Reads a line from stdin, returning None at the end of input. A terminal
+/// gets the line editor, anything else is read as it is.
+fn read_line(prompt: &str, history: &[String]) -> Option<String> {
+    let stdin = std::io::stdin();
+    if stdin.is_terminal() {
+        if let Some(settings) = stty(&["-g"]) {
+            let _restore = Restore(settings);
+            stty(&["-icanon", "-echo", "-isig", "min", "1"]);
+            return edit_line(prompt, history);
+        }
+    }
+    let mut line = String::new();
+    if stdin.lock().read_line(&mut line).expect("um") == 0 {
+        return None;
+    }
+    Some(line.trim_end_matches(&['\r', '\n'][..]).to_string())
+}
+
+/// Puts the terminal settings back, even if the line editor panics
+struct Restore(String);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        stty(&[self.0.trim()]);
+    }
+}
+
+/// Runs `stty` on the terminal, returning what it printed if it worked
+fn stty(args: &[&str]) -> Option<String> {
+    let output = (Command::new("stty").args(args))
+        .stdin(Stdio::inherit())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8(output.stdout).ok()
+}
+
+/// Edits a line with the terminal in raw mode, drawing it after `prompt`
+fn edit_line(prompt: &str, history: &[String]) -> Option<String> {
+    let stdin = std::io::stdin();
+    let mut bytes = stdin.lock().bytes().map(|byte| byte.expect("um"));
+    let mut line = Vec::new();
+    let mut cursor = 0;
+    // Where in the history the line came from, and what was typed before
+    // going up into it
+    let mut index = history.len();
+    let mut draft = Vec::new();
+    loop {
+        match bytes.next()? {
+            b'\r' | b'\n' => {
+                println!();
+                return Some(String::from_utf8_lossy(&line).into_owned());
+            }
+            // Ctrl-C, or Ctrl-D on an empty line
+            3 => return None,
+            4 if line.is_empty() => return None,
+            // Ctrl-A, Ctrl-E, Ctrl-U
+            1 => cursor = 0,
+            5 => cursor = line.len(),
+            21 => {
+                line.drain(..cursor);
+                cursor = 0;
+            }
+            127 | 8 if cursor > 0 => {
+                cursor -= 1;
+                line.remove(cursor);
+            }
+            byte @ b' '..=b'~' => {
+                line.insert(cursor, byte);
+                cursor += 1;
+            }
+            27 => {
+                // `ESC [ A`, `ESC O H`, `ESC [ 3 ~` and so on. Parameters run
+                // up to a final byte, so `ESC [ 1 ; 5 C` is still just right.
+                let kind = bytes.next()?;
+                let mut key = bytes.next()?;
+                if kind == b'[' {
+                    let mut params = Vec::new();
+                    while !(0x40..=0x7e).contains(&key) {
+                        params.push(key);
+                        key = bytes.next()?;
+                    }
+                    if key == b'~' {
+                        key = match &params[..] {
+                            b"1" | b"7" => b'H',
+                            b"4" | b"8" => b'F',
+                            b"3" => b'~',
+                            _ => 0,
+                        };
+                    }
+                }
+                match key {
+                    b'A' if index > 0 => {
+                        if index == history.len() {
+                            draft = line.clone();
+                        }
+                        index -= 1;
+                        line = history[index].as_bytes().to_vec();
+                        cursor = line.len();
+                    }
+                    b'B' if index < history.len() => {
+                        index += 1;
+                        line = match history.get(index) {
+                            Some(entry) => entry.as_bytes().to_vec(),
+                            None => draft.clone(),
+                        };
+                        cursor = line.len();
+                    }
+                    b'C' => cursor = (cursor + 1).min(line.len()),
+                    b'D' => cursor = cursor.saturating_sub(1),
+                    b'H' => cursor = 0,
+                    b'F' => cursor = line.len(),
+                    b'~' if cursor < line.len() => {
+                        line.remove(cursor);
+                    }
+                    _ => (),
+                }
+            }
+            _ => (),
+        }
+
+        print!("\r{}{}\x1b[K", prompt, String::from_utf8_lossy(&line));
+        if cursor < line.len() {
+            print!("\x1b[{}D", line.len() - cursor);
+        }
+        std::io::stdout().flush().expect("um");
+    }
+}
@@ -0,0 +1,330 @@
+use aoc2019::intcode::debugger::{Debugger, Stop};
+use aoc2019::intcode::trace::TraceFormat;
+use aoc2019::intcode::{cpu, parse, Cpu};
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+
+static HELP: &str = "\
+Commands:
+  s, step [n]            execute n instructions (default 1)
+  n, next                run until the instruction after this one
+  c, continue            run until a breakpoint, watchpoint or halt
+  b, break <addr>        break when pc reaches addr
+  b, break op <op>       break before any instruction with opcode op
+  w, watch <addr>        stop when memory at addr changes
+  d, delete [addr]       remove a breakpoint or watchpoint, or all of them
+  op-delete <op>         remove an opcode breakpoint
+  i, info                list breakpoints, watchpoints and queued input
+  r, regs                show pc, rbo and the current instruction
+  x, mem <addr> [n]      show n words of memory (default 8)
+  l, dis [addr] [n]      disassemble n instructions (default at pc, 10)
+  set mem <addr> <val>   write to memory
+  set pc <val>           move the program counter
+  set rbo <val>          change the relative base
+  in, input <vals...>    queue input values
+  in-file <path>         queue input values read from a file
+  trace <path> [bin]     record executed instructions as JSON lines (or binary)
+  trace off              stop recording
+  profile on|off         start or stop counting executed instructions
+  profile [csv <path>]   show the profile, or write it as CSV
+  save <path>            write a save state of the cpu
+  load <path>            replace the cpu with a save state
+  q, quit                exit the debugger";
+
+fn main() {
+    let path = match std::env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("Usage: intdbg <program.txt>");
+            std::process::exit(1);
+        }
+    };
+    let program = std::fs::read_to_string(&path).expect("Couldn't read program");
+    let mut dbg = Debugger::new(Cpu::new(parse(&program)));
+
+    println!(
+        "Loaded {} words from {}, type `help` for commands",
+        dbg.cpu.memory.len(),
+        path
+    );
+    show_regs(&dbg);
+
+    let stdin = std::io::stdin();
+    let mut last = String::new();
+    loop {
+        print!("(intdbg) ");
+        std::io::stdout().flush().expect("um");
+
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).expect("um") == 0 {
+            break;
+        }
+        // An empty line repeats the last command, like gdb
+        let line = match line.trim() {
+            "" => last.clone(),
+            line => line.to_string(),
+        };
+        last = line.clone();
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match execute(&mut dbg, &words) {
+            Ok(true) => (),
+            Ok(false) => break,
+            Err(msg) => println!("❌ {}", msg),
+        }
+    }
+}
+
+/// Runs one command, returning false if the debugger should exit
+fn execute(dbg: &mut Debugger, words: &[&str]) -> Result<bool, String> {
+    let (&cmd, args) = match words.split_first() {
+        Some(split) => split,
+        None => return Ok(true),
+    };
+
+    match cmd {
+        "help" | "h" | "?" => println!("{}", HELP),
+        "q" | "quit" => {
+            dbg.cpu.stop_trace().map_err(|e| e.to_string())?;
+            return Ok(false);
+        }
+        "s" | "step" => {
+            let count = arg(args, 0)?.unwrap_or(1);
+            run(dbg, |dbg| dbg.step(count));
+        }
+        "n" | "next" => run(dbg, Debugger::step_over),
+        "c" | "continue" => run(dbg, Debugger::cont),
+        "b" | "break" => match args {
+            ["op", op] => {
+                let op = opcode(op)?;
+                dbg.op_breakpoints.insert(op);
+                println!("Breaking on opcode {}", op);
+            }
+            _ => {
+                let addr = required(args, 0)?;
+                dbg.breakpoints.insert(addr);
+                println!("Breakpoint set at {}", addr);
+            }
+        },
+        "w" | "watch" => {
+            let addr = required(args, 0)?;
+            dbg.watch(addr);
+            println!("Watching {} (currently {})", addr, dbg.peek(addr));
+        }
+        "d" | "delete" => match arg::<usize>(args, 0)? {
+            Some(addr) => {
+                let found = dbg.breakpoints.remove(&addr) | dbg.watchpoints.remove(&addr).is_some();
+                if !found {
+                    return Err(format!("Nothing set at {}", addr));
+                }
+            }
+            None => {
+                dbg.breakpoints.clear();
+                dbg.op_breakpoints.clear();
+                dbg.watchpoints.clear();
+            }
+        },
+        "op-delete" => {
+            let op = opcode(args.first().ok_or("Expected an opcode")?)?;
+            dbg.op_breakpoints.remove(&op);
+        }
+        "i" | "info" => {
+            println!("Breakpoints: {:?}", dbg.breakpoints);
+            println!("Opcode breakpoints: {:?}", dbg.op_breakpoints);
+            println!(
+                "Watchpoints: {:?}",
+                dbg.watchpoints.keys().collect::<Vec<_>>()
+            );
+            println!("Queued input: {:?}", dbg.inputs);
+        }
+        "r" | "regs" => show_regs(dbg),
+        "x" | "mem" => {
+            let addr: usize = required(args, 0)?;
+            let count: usize = arg(args, 1)?.unwrap_or(8);
+            let end = addr
+                .checked_add(count)
+                .ok_or_else(|| format!("Bad range {} + {}", addr, count))?;
+            for start in (addr..end).step_by(8) {
+                let row = start..end.min(start.saturating_add(8));
+                let values: Vec<_> = row.map(|a| format!("{:>8}", dbg.peek(a))).collect();
+                println!("{:>6}: {}", start, values.join(""));
+            }
+        }
+        "l" | "dis" => {
+            let mut addr = arg(args, 0)?.unwrap_or(dbg.cpu.pc);
+            let count = arg(args, 1)?.unwrap_or(10);
+            for _ in 0..count {
+                let marker = if addr == dbg.cpu.pc { "=>" } else { "  " };
+                let size = match dbg.cpu.decode(addr) {
+                    Ok(instr) => {
+                        println!("{} {:>6}: {}", marker, addr, instr);
+                        instr.size()
+                    }
+                    Err(_) => {
+                        println!("{} {:>6}: .data {}", marker, addr, dbg.peek(addr));
+                        1
+                    }
+                };
+                addr = match addr.checked_add(size) {
+                    Some(next) => next,
+                    None => break,
+                };
+            }
+        }
+        "set" => match args {
+            ["mem", addr, value] => {
+                dbg.poke(number(addr)?, number(value)?)
+                    .map_err(|err| err.to_string())?;
+            }
+            ["pc", value] => dbg.cpu.pc = number(value)?,
+            ["rbo", value] => dbg.cpu.rbo = number(value)?,
+            _ => return Err("Usage: set mem <addr> <val> | set pc <val> | set rbo <val>".into()),
+        },
+        "in" | "input" => {
+            for value in args {
+                dbg.inputs.push_back(number(value)?);
+            }
+        }
+        "in-file" => {
+            let path = args.first().ok_or("Expected a path")?;
+            let data = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+            let values = data.split(|c: char| c == ',' || c.is_whitespace());
+            for value in values.filter(|v| !v.is_empty()) {
+                dbg.inputs.push_back(number(value)?);
+            }
+            println!("Queued input: {:?}", dbg.inputs);
+        }
+        "trace" => match args {
+            ["off"] => {
+                let count = dbg.cpu.tracer().map_or(0, |t| t.count());
+                dbg.cpu.stop_trace().map_err(|e| e.to_string())?;
+                println!("Recorded {} instructions", count);
+            }
+            [path] | [path, "json"] | [path, "bin"] => {
+                let format = match args.get(1) {
+                    Some(&"bin") => TraceFormat::Binary,
+                    _ => TraceFormat::Json,
+                };
+                let file = File::create(path).map_err(|e| e.to_string())?;
+                dbg.cpu.stop_trace().map_err(|e| e.to_string())?;
+                dbg.cpu.start_trace(format, BufWriter::new(file));
+                println!("Tracing to {}", path);
+            }
+            _ => return Err("Usage: trace <path> [json|bin] | trace off".into()),
+        },
+        "profile" => match args {
+            ["on"] => dbg.cpu.start_profile(),
+            ["off"] => {
+                dbg.cpu.stop_profile();
+            }
+            [] => print!("{}", dbg.cpu.profile().ok_or("Profiling is off")?),
+            ["csv", path] => {
+                let profile = dbg.cpu.profile().ok_or("Profiling is off")?;
+                let mut file = BufWriter::new(File::create(path).map_err(|e| e.to_string())?);
+                profile.write_csv(&mut file).map_err(|e| e.to_string())?;
+                file.flush().map_err(|e| e.to_string())?;
+            }
+            _ => return Err("Usage: profile [on|off|csv <path>]".into()),
+        },
+        "save" => {
+            let path = args.first().ok_or("Expected a path")?;
+            let mut file = BufWriter::new(File::create(path).map_err(|e| e.to_string())?);
+            dbg.cpu.save(&mut file).map_err(|e| e.to_string())?;
+            file.flush().map_err(|e| e.to_string())?;
+            println!("Saved to {}", path);
+        }
+        "load" => {
+            let path = args.first().ok_or("Expected a path")?;
+            let file = BufReader::new(File::open(path).map_err(|e| e.to_string())?);
+            dbg.cpu.stop_trace().map_err(|e| e.to_string())?;
+            dbg.cpu = Cpu::load(file).map_err(|e| e.to_string())?;
+            for addr in dbg.watchpoints.keys().copied().collect::<Vec<_>>() {
+                dbg.watch(addr);
+            }
+            show_regs(dbg);
+        }
+        _ => return Err(format!("Unknown command `{}`, try `help`", cmd)),
+    }
+
+    Ok(true)
+}
+
+/// Runs an execution command, asking for input whenever the program blocks on it
+fn run(dbg: &mut Debugger, mut command: impl FnMut(&mut Debugger) -> Stop) {
+    loop {
+        let stop = command(dbg);
+        for value in dbg.outputs.drain(..) {
+            match value {
+                10 | 32..=126 => println!("Output: {} ({:?})", value, value as u8 as char),
+                _ => println!("Output: {}", value),
+            }
+        }
+
+        match stop {
+            Stop::Done => (),
+            Stop::Breakpoint(pc) => println!("Breakpoint at {}", pc),
+            Stop::Opcode(op) => println!("Opcode breakpoint on {}", op),
+            Stop::Watchpoint { addr, old, new } => {
+                println!("Watchpoint: [{}] changed from {} to {}", addr, old, new)
+            }
+            Stop::Halt => println!("Program halted"),
+            Stop::Error(err) => println!("❌ {}", err),
+            Stop::NeedInput => {
+                if prompt_input(dbg) {
+                    continue;
+                }
+            }
+        }
+        break;
+    }
+    show_regs(dbg);
+}
+
+/// Reads input values from the user, returning false on an empty line
+fn prompt_input(dbg: &mut Debugger) -> bool {
+    loop {
+        print!("Waiting for input (empty to stop): ");
+        std::io::stdout().flush().expect("um");
+        let mut line = String::new();
+        std::io::stdin().read_line(&mut line).expect("um");
+
+        let values: Result<Vec<i64>, _> = line.split_whitespace().map(str::parse).collect();
+        match values {
+            Ok(ref values) if values.is_empty() => return false,
+            Ok(values) => {
+                dbg.inputs.extend(values);
+                return true;
+            }
+            Err(_) => println!("❌"),
+        }
+    }
+}
+
+fn show_regs(dbg: &Debugger) {
+    let instr = match dbg.cpu.decode(dbg.cpu.pc) {
+        Ok(instr) => instr.to_string(),
+        Err(err) => err.kind.to_string(),
+    };
+    println!("pc = {}, rbo = {}: {}", dbg.cpu.pc, dbg.cpu.rbo, instr);
+}
+
+fn opcode(op: &str) -> Result<i64, String> {
+    match cpu::opcode(op) {
+        Some(op) => Ok(op),
+        None => number(op),
+    }
+}
+
+fn number<T: std::str::FromStr>(word: &str) -> Result<T, String> {
+    word.parse()
+        .map_err(|_| format!("Invalid number `{}`", word))
+}
+
+fn arg<T: std::str::FromStr>(args: &[&str], i: usize) -> Result<Option<T>, String> {
+    args.get(i).map(|word| number(word)).transpose()
+}
+
+fn required<T: std::str::FromStr>(args: &[&str], i: usize) -> Result<T, String> {
+    arg(args, i)?.ok_or_else(|| format!("Expected argument {}", i + 1))
+}
@@ -0,0 +1,538 @@
+//! Assembler for a small textual Intcode language.
+//!
+//! ```text
+//! ; Echo numbers until a zero is read
+//!         arb #stack
+//! loop:   in value
+//!         jz value, #done
+//!         out value
+//!         jmp #loop
+//! done:   hlt
+//! value:  .data 0
+//! stack:
+//! ```
+//!
+//! Operands are position mode by default, `#` selects immediate mode and `@`
+//! relative mode. Operand values may be numbers, labels or sums of them like
+//! `table+2`, and labels can't start with `.`. `.data` emits numbers, labels
+//! and string literals (as ASCII), and `.zero n` emits `n` zeroes. Programs
+//! can be at most `MAX_WORDS` long.
+//!
+//! The relative base doubles as a stack pointer for the helper instructions:
+//! `push a` and `pop a` move a value on or off the stack, `call a` pushes the
+//! return address and jumps, `ret` pops it and jumps back, and `jmp a` jumps
+//! unconditionally. Programs using them should start with `arb #stack`.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl Error for AsmError {}
+
+/// Longest program `assemble` will produce. Anything longer is far more
+/// likely to be a typo in a `.zero` count than a real program.
+pub const MAX_WORDS: usize = 1 << 24;
+
+pub fn assemble(source: &str) -> Result<Vec<i64>, AsmError> {
+    let mut asm = Assembler::default();
+    for (i, line) in source.lines().enumerate() {
+        asm.line(i + 1, line)?;
+    }
+    asm.finish()
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Mode {
+    Position = 0,
+    Immediate = 1,
+    Relative = 2,
+}
+
+#[derive(Clone)]
+enum Atom {
+    Number(i64),
+    Label(String),
+}
+
+/// Sum of signed atoms, with the column of each for error reporting
+#[derive(Clone)]
+struct Expr {
+    terms: Vec<(i64, Atom, usize)>,
+}
+
+impl Expr {
+    fn number(value: i64) -> Expr {
+        Expr {
+            terms: vec![(1, Atom::Number(value), 0)],
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Arg {
+    mode: Mode,
+    value: Expr,
+}
+
+impl Arg {
+    fn new(mode: Mode, value: i64) -> Arg {
+        Arg {
+            mode,
+            value: Expr::number(value),
+        }
+    }
+}
+
+enum Word {
+    Instr(i64, Vec<Mode>),
+    Value(Expr),
+    /// A run of zeroes, kept as a count so big `.zero`s stay cheap
+    Zeros(usize),
+}
+
+impl Word {
+    fn len(&self) -> usize {
+        match self {
+            Word::Zeros(count) => *count,
+            _ => 1,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Assembler {
+    /// Emitted words along with the line they came from
+    words: Vec<(Word, usize)>,
+    /// Address of the next word emitted
+    len: usize,
+    labels: HashMap<String, i64>,
+}
+
+impl Assembler {
+    fn line(&mut self, line: usize, text: &str) -> Result<(), AsmError> {
+        let tokens = lex(line, text)?;
+        let mut p = Parser {
+            line,
+            tokens: &tokens,
+            pos: 0,
+            end: text.len() + 1,
+        };
+
+        while let (Some(Token::Ident(name)), Some(Token::Colon)) = (p.peek(0), p.peek(1)) {
+            // Names starting with `.` are directives, they can't be operands
+            if name.starts_with('.') {
+                return Err(p.error(format!("label `{}` can't start with `.`", name)));
+            }
+            if self.labels.contains_key(name) {
+                return Err(p.error(format!("label `{}` is already defined", name)));
+            }
+            self.labels.insert(name.clone(), self.len as i64);
+            p.pos += 2;
+        }
+
+        let name = match p.next() {
+            None => return Ok(()),
+            Some(Token::Ident(name)) => name.to_ascii_lowercase(),
+            Some(tok) => return Err(p.error_prev(format!("expected instruction, found {}", tok))),
+        };
+
+        match name.as_str() {
+            ".data" => self.data(&mut p)?,
+            ".zero" => {
+                let count = match p.next() {
+                    Some(&Token::Number(n)) if n >= 0 => n,
+                    _ => return Err(p.error_prev("`.zero` expects a non-negative count")),
+                };
+                if count > MAX_WORDS.saturating_sub(self.len) as i64 {
+                    let message = format!("`.zero` would go past {} words", MAX_WORDS);
+                    return Err(p.error_prev(message));
+                }
+                self.emit(line, Word::Zeros(count as usize));
+            }
+            _ => self.instruction(&mut p, &name)?,
+        }
+
+        match p.peek(0) {
+            None => Ok(()),
+            Some(tok) => Err(p.error(format!("unexpected {}", tok))),
+        }
+    }
+
+    fn data(&mut self, p: &mut Parser) -> Result<(), AsmError> {
+        loop {
+            match p.peek(0) {
+                Some(Token::Str(s)) => {
+                    for b in s.bytes() {
+                        self.emit(p.line, Word::Value(Expr::number(b as i64)));
+                    }
+                    p.pos += 1;
+                }
+                _ => {
+                    let value = p.expr()?;
+                    self.emit(p.line, Word::Value(value));
+                }
+            }
+            if !p.eat(&Token::Comma) {
+                break Ok(());
+            }
+        }
+    }
+
+    fn instruction(&mut self, p: &mut Parser, name: &str) -> Result<(), AsmError> {
+        let (stores, real): (&[bool], _) = match name {
+            "add" => (&[false, false, true], Some(1)),
+            "mul" => (&[false, false, true], Some(2)),
+            "in" => (&[true], Some(3)),
+            "out" => (&[false], Some(4)),
+            "jnz" => (&[false, false], Some(5)),
+            "jz" => (&[false, false], Some(6)),
+            "lt" => (&[false, false, true], Some(7)),
+            "eq" => (&[false, false, true], Some(8)),
+            "arb" => (&[false], Some(9)),
+            "hlt" => (&[], Some(99)),
+            "push" | "jmp" | "call" => (&[false], None),
+            "pop" => (&[true], None),
+            "ret" => (&[], None),
+            _ => return Err(p.error_prev(format!("unknown instruction `{}`", name))),
+        };
+
+        let mut args = Vec::with_capacity(stores.len());
+        for (i, &store) in stores.iter().enumerate() {
+            if i > 0 && !p.eat(&Token::Comma) {
+                return Err(p.error(format!(
+                    "`{}` expects {} operands, found {}",
+                    name,
+                    stores.len(),
+                    i
+                )));
+            }
+            let column = p.column();
+            let arg = p.arg()?;
+            if store && arg.mode == Mode::Immediate {
+                return Err(AsmError {
+                    line: p.line,
+                    column,
+                    message: format!(
+                        "operand {} of `{}` is written to and can't be immediate",
+                        i + 1,
+                        name
+                    ),
+                });
+            }
+            args.push(arg);
+        }
+        if p.peek(0) == Some(&Token::Comma) {
+            return Err(p.error(format!(
+                "`{}` expects {} operands, found more",
+                name,
+                stores.len()
+            )));
+        }
+
+        if let Some(opcode) = real {
+            self.emit_instr(p.line, opcode, args);
+            return Ok(());
+        }
+
+        let top = || Arg::new(Mode::Relative, 0);
+        let zero = || Arg::new(Mode::Immediate, 0);
+        let arb = |n| vec![Arg::new(Mode::Immediate, n)];
+        let mut args = args.into_iter();
+        match name {
+            "push" => {
+                self.emit_instr(p.line, 1, vec![args.next().unwrap(), zero(), top()]);
+                self.emit_instr(p.line, 9, arb(1));
+            }
+            "pop" => {
+                self.emit_instr(p.line, 9, arb(-1));
+                self.emit_instr(p.line, 1, vec![top(), zero(), args.next().unwrap()]);
+            }
+            "jmp" => self.emit_instr(p.line, 6, vec![zero(), args.next().unwrap()]),
+            "call" => {
+                // add (4) + arb (2) + jz (3)
+                let ret = self.len as i64 + 9;
+                self.emit_instr(
+                    p.line,
+                    1,
+                    vec![Arg::new(Mode::Immediate, ret), zero(), top()],
+                );
+                self.emit_instr(p.line, 9, arb(1));
+                self.emit_instr(p.line, 6, vec![zero(), args.next().unwrap()]);
+            }
+            "ret" => {
+                self.emit_instr(p.line, 9, arb(-1));
+                self.emit_instr(p.line, 6, vec![zero(), top()]);
+            }
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    fn emit(&mut self, line: usize, word: Word) {
+        self.len += word.len();
+        self.words.push((word, line));
+    }
+
+    fn emit_instr(&mut self, line: usize, opcode: i64, args: Vec<Arg>) {
+        let modes = args.iter().map(|arg| arg.mode).collect();
+        self.emit(line, Word::Instr(opcode, modes));
+        for arg in args {
+            self.emit(line, Word::Value(arg.value));
+        }
+    }
+
+    fn finish(self) -> Result<Vec<i64>, AsmError> {
+        // Point at whatever pushed the program over the limit
+        let mut end = 0;
+        let too_long = self.words.iter().find(|(word, _)| {
+            end += word.len();
+            end > MAX_WORDS
+        });
+        if let Some(&(_, line)) = too_long {
+            return Err(AsmError {
+                line,
+                column: 1,
+                message: format!("program is longer than {} words", MAX_WORDS),
+            });
+        }
+
+        let labels = self.labels;
+        let mut program = Vec::with_capacity(self.len);
+        for (word, line) in self.words {
+            match word {
+                Word::Instr(opcode, modes) => {
+                    let instr = modes.iter().enumerate().fold(opcode, |instr, (i, &mode)| {
+                        instr + mode as i64 * 10i64.pow(i as u32 + 2)
+                    });
+                    program.push(instr);
+                }
+                Word::Value(expr) => {
+                    let mut value = 0i64;
+                    for (sign, atom, column) in expr.terms {
+                        let term = match atom {
+                            Atom::Number(n) => n,
+                            Atom::Label(name) => *labels.get(&name).ok_or_else(|| AsmError {
+                                line,
+                                column,
+                                message: format!("undefined label `{}`", name),
+                            })?,
+                        };
+                        value = value.wrapping_add(sign * term);
+                    }
+                    program.push(value);
+                }
+                Word::Zeros(count) => program.resize(program.len() + count, 0),
+            }
+        }
+        Ok(program)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(i64),
+    Str(String),
+    Comma,
+    Colon,
+    Hash,
+    At,
+    Plus,
+    Minus,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Ident(name) => write!(f, "`{}`", name),
+            Token::Number(n) => write!(f, "`{}`", n),
+            Token::Str(s) => write!(f, "{:?}", s),
+            Token::Comma => f.write_str("`,`"),
+            Token::Colon => f.write_str("`:`"),
+            Token::Hash => f.write_str("`#`"),
+            Token::At => f.write_str("`@`"),
+            Token::Plus => f.write_str("`+`"),
+            Token::Minus => f.write_str("`-`"),
+        }
+    }
+}
+
+/// Splits a line into tokens paired with their 1-based columns
+fn lex(line: usize, text: &str) -> Result<Vec<(usize, Token)>, AsmError> {
+    let error = |column, message| AsmError {
+        line,
+        column,
+        message,
+    };
+
+    let mut tokens = Vec::new();
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let column = i + 1;
+        let token = match c {
+            ';' => break,
+            c if c.is_whitespace() => continue,
+            ',' => Token::Comma,
+            ':' => Token::Colon,
+            '#' => Token::Hash,
+            '@' => Token::At,
+            '+' => Token::Plus,
+            '-' => Token::Minus,
+            '0'..='9' => {
+                let mut end = i + 1;
+                while let Some(&(j, c)) = chars.peek() {
+                    if !c.is_ascii_alphanumeric() {
+                        break;
+                    }
+                    end = j + c.len_utf8();
+                    chars.next();
+                }
+                let digits = &text[i..end];
+                match digits.parse() {
+                    Ok(n) => Token::Number(n),
+                    Err(_) => return Err(error(column, format!("invalid number `{}`", digits))),
+                }
+            }
+            c if c == '_' || c == '.' || c.is_alphabetic() => {
+                let mut name = c.to_string();
+                while let Some(&(_, c)) = chars.peek() {
+                    if c != '_' && !c.is_alphanumeric() {
+                        break;
+                    }
+                    name.push(c);
+                    chars.next();
+                }
+                Token::Ident(name)
+            }
+            '"' => {
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        None => return Err(error(column, "unterminated string".into())),
+                        Some((_, '"')) => break,
+                        Some((j, '\\')) => match chars.next() {
+                            Some((_, 'n')) => s.push('\n'),
+                            Some((_, 't')) => s.push('\t'),
+                            Some((_, '0')) => s.push('\0'),
+                            Some((_, '\\')) => s.push('\\'),
+                            Some((_, '"')) => s.push('"'),
+                            _ => return Err(error(j + 1, "invalid escape sequence".into())),
+                        },
+                        Some((j, c)) if !c.is_ascii() => {
+                            return Err(error(j + 1, format!("non-ASCII character `{}`", c)));
+                        }
+                        Some((_, c)) => s.push(c),
+                    }
+                }
+                Token::Str(s)
+            }
+            c => return Err(error(column, format!("unexpected character `{}`", c))),
+        };
+        tokens.push((column, token));
+    }
+
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    line: usize,
+    tokens: &'a [(usize, Token)],
+    pos: usize,
+    /// Column just past the end of the line, for errors about missing tokens
+    end: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self, ahead: usize) -> Option<&'a Token> {
+        self.tokens.get(self.pos + ahead).map(|(_, tok)| tok)
+    }
+
+    fn next(&mut self) -> Option<&'a Token> {
+        let tok = self.tokens.get(self.pos).map(|(_, tok)| tok);
+        self.pos += 1;
+        tok
+    }
+
+    fn eat(&mut self, tok: &Token) -> bool {
+        let found = self.peek(0) == Some(tok);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn column(&self) -> usize {
+        self.tokens.get(self.pos).map_or(self.end, |&(col, _)| col)
+    }
+
+    fn error(&self, message: impl Into<String>) -> AsmError {
+        AsmError {
+            line: self.line,
+            column: self.column(),
+            message: message.into(),
+        }
+    }
+
+    /// Error pointing at the token that was just consumed
+    fn error_prev(&self, message: impl Into<String>) -> AsmError {
+        AsmError {
+            line: self.line,
+            column: self
+                .tokens
+                .get(self.pos - 1)
+                .map_or(self.end, |&(col, _)| col),
+            message: message.into(),
+        }
+    }
+
+    fn arg(&mut self) -> Result<Arg, AsmError> {
+        let mode = if self.eat(&Token::Hash) {
+            Mode::Immediate
+        } else if self.eat(&Token::At) {
+            Mode::Relative
+        } else {
+            Mode::Position
+        };
+        let value = self.expr()?;
+        Ok(Arg { mode, value })
+    }
+
+    fn expr(&mut self) -> Result<Expr, AsmError> {
+        let mut terms = Vec::new();
+        let mut sign = if self.eat(&Token::Minus) { -1 } else { 1 };
+        loop {
+            let column = self.column();
+            let atom = match self.next() {
+                Some(&Token::Number(n)) => Atom::Number(n),
+                Some(Token::Ident(name)) if !name.starts_with('.') => Atom::Label(name.clone()),
+                Some(tok) => {
+                    let message = format!("expected number or label, found {}", tok);
+                    return Err(self.error_prev(message));
+                }
+                None => return Err(self.error_prev("expected number or label")),
+            };
+            terms.push((sign, atom, column));
+
+            sign = if self.eat(&Token::Plus) {
+                1
+            } else if self.eat(&Token::Minus) {
+                -1
+            } else {
+                break Ok(Expr { terms });
+            };
+        }
+    }
+}
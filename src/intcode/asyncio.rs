@@ -0,0 +1,129 @@
+//! I/O for `Cpu::run_async`. Nothing here depends on a particular runtime,
+//! the `executor` module has a small one for running many cpus together.
+
+use super::{Flow, Input, Word};
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::future::{self, Future};
+use std::rc::Rc;
+use std::task::{Poll, Waker};
+
+/// `IO` for `Cpu::run_async`, which can wait for input or for room to output
+pub trait AsyncIO<W = i64> {
+    fn input(&mut self) -> impl Future<Output = Input<W>>;
+    fn output(&mut self, value: W) -> impl Future<Output = Flow>;
+}
+
+impl<W, T: AsyncIO<W>> AsyncIO<W> for &mut T {
+    fn input(&mut self) -> impl Future<Output = Input<W>> {
+        T::input(self)
+    }
+    fn output(&mut self, value: W) -> impl Future<Output = Flow> {
+        T::output(self, value)
+    }
+}
+
+struct Shared<W> {
+    queue: VecDeque<W>,
+    waker: Option<Waker>,
+    senders: usize,
+}
+
+/// An unbounded channel for cpus running on the same thread
+pub fn channel<W>() -> (Sender<W>, Receiver<W>) {
+    let shared = Rc::new(RefCell::new(Shared {
+        queue: VecDeque::new(),
+        waker: None,
+        senders: 1,
+    }));
+    (Sender(shared.clone()), Receiver(shared))
+}
+
+pub struct Sender<W>(Rc<RefCell<Shared<W>>>);
+
+impl<W> Sender<W> {
+    pub fn send(&self, value: W) {
+        let mut shared = self.0.borrow_mut();
+        shared.queue.push_back(value);
+        if let Some(waker) = shared.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl<W> Clone for Sender<W> {
+    fn clone(&self) -> Self {
+        self.0.borrow_mut().senders += 1;
+        Sender(self.0.clone())
+    }
+}
+
+impl<W> Drop for Sender<W> {
+    fn drop(&mut self) {
+        let mut shared = self.0.borrow_mut();
+        shared.senders -= 1;
+        if shared.senders == 0 {
+            if let Some(waker) = shared.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+pub struct Receiver<W>(Rc<RefCell<Shared<W>>>);
+
+impl<W> Receiver<W> {
+    /// Waits for the next value, or `None` once every sender is gone
+    pub async fn recv(&mut self) -> Option<W> {
+        future::poll_fn(|cx| {
+            let mut shared = self.0.borrow_mut();
+            match shared.queue.pop_front() {
+                Some(value) => Poll::Ready(Some(value)),
+                None if shared.senders == 0 => Poll::Ready(None),
+                None => {
+                    shared.waker = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            }
+        })
+        .await
+    }
+
+    /// The next value if one has already been sent
+    pub fn try_recv(&mut self) -> Option<W> {
+        self.0.borrow_mut().queue.pop_front()
+    }
+}
+
+/// `ChannelIO` without a thread per cpu. Stops the cpu once every sender
+/// of its input is gone.
+pub struct AsyncChannelIO<W = i64> {
+    input: Receiver<W>,
+    output: Sender<W>,
+    pub last_output: W,
+}
+
+impl<W: Word> AsyncChannelIO<W> {
+    pub fn new(input: Receiver<W>, output: Sender<W>) -> Self {
+        AsyncChannelIO {
+            input,
+            output,
+            last_output: W::zero(),
+        }
+    }
+}
+
+impl<W: Word> AsyncIO<W> for AsyncChannelIO<W> {
+    async fn input(&mut self) -> Input<W> {
+        match self.input.recv().await {
+            Some(value) => Input::Value(value),
+            None => Input::Stop,
+        }
+    }
+    async fn output(&mut self, value: W) -> Flow {
+        self.last_output = value.clone();
+        self.output.send(value);
+        Flow::Continue
+    }
+}
@@ -0,0 +1,143 @@
+//! Cpus wired together, each node's outputs feeding the inputs of the nodes
+//! it's connected to. Any shape works, including fan-out, fan-in and
+//! feedback loops like day 7's amplifiers.
+//!
+//! ```text
+//! let mut circuit = Circuit::new();
+//! let a = circuit.add_node(&program, &[phase_a, 0]);
+//! let b = circuit.add_node(&program, &[phase_b]);
+//! circuit.connect(a, b);
+//! circuit.connect(b, a);
+//! circuit.run()?;
+//! let result = circuit.outputs(b).last();
+//! ```
+
+use super::{Cpu, CpuError, CpuResult};
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CircuitError {
+    Cpu {
+        node: usize,
+        error: CpuError,
+    },
+    /// Every node left is waiting on input that will never come
+    Deadlock {
+        waiting: Vec<usize>,
+    },
+}
+
+impl fmt::Display for CircuitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CircuitError::Cpu { node, error } => write!(f, "Node {}: {}", node, error),
+            CircuitError::Deadlock { waiting } => {
+                write!(f, "Deadlock, nodes {:?} are waiting on input", waiting)
+            }
+        }
+    }
+}
+
+impl Error for CircuitError {}
+
+struct Node {
+    cpu: Cpu,
+    inputs: VecDeque<i64>,
+    outputs: Vec<i64>,
+    targets: Vec<usize>,
+    halted: bool,
+}
+
+#[derive(Default)]
+pub struct Circuit {
+    nodes: Vec<Node>,
+}
+
+impl Circuit {
+    pub fn new() -> Self {
+        Circuit::default()
+    }
+
+    /// Adds a node running `program` that reads `inputs` before anything
+    /// sent to it, returning its id
+    pub fn add_node(&mut self, program: &[i64], inputs: &[i64]) -> usize {
+        self.nodes.push(Node {
+            cpu: Cpu::new(program.to_vec()),
+            inputs: inputs.iter().copied().collect(),
+            outputs: Vec::new(),
+            targets: Vec::new(),
+            halted: false,
+        });
+        self.nodes.len() - 1
+    }
+
+    /// Sends every output of `from` to `to` as well as anywhere else it
+    /// already goes. Inputs from several nodes arrive in the order they
+    /// were output.
+    pub fn connect(&mut self, from: usize, to: usize) {
+        assert!(to < self.nodes.len(), "no node {}", to);
+        self.nodes[from].targets.push(to);
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Everything `node` has output so far
+    pub fn outputs(&self, node: usize) -> &[i64] {
+        &self.nodes[node].outputs
+    }
+
+    /// Runs nodes in turn until they've all halted
+    pub fn run(&mut self) -> Result<(), CircuitError> {
+        loop {
+            let mut progress = false;
+            for node in 0..self.nodes.len() {
+                progress |= self
+                    .run_node(node)
+                    .map_err(|error| CircuitError::Cpu { node, error })?;
+            }
+            if self.nodes.iter().all(|node| node.halted) {
+                break Ok(());
+            }
+            if !progress {
+                let waiting = (0..self.nodes.len())
+                    .filter(|&node| !self.nodes[node].halted)
+                    .collect();
+                break Err(CircuitError::Deadlock { waiting });
+            }
+        }
+    }
+
+    /// Runs `node` until it halts or runs out of input, returning whether
+    /// it did anything
+    fn run_node(&mut self, node: usize) -> Result<bool, CpuError> {
+        let mut progress = false;
+        while !self.nodes[node].halted {
+            let current = &mut self.nodes[node];
+            match current.cpu.try_resume()? {
+                CpuResult::Halt => current.halted = true,
+                CpuResult::Input => match current.inputs.pop_front() {
+                    Some(input) => current.cpu.try_input(input)?,
+                    None => return Ok(progress),
+                },
+                CpuResult::Output(output) => {
+                    current.outputs.push(output);
+                    for i in 0..current.targets.len() {
+                        let target = self.nodes[node].targets[i];
+                        self.nodes[target].inputs.push_back(output);
+                    }
+                }
+            }
+            progress = true;
+        }
+        Ok(progress)
+    }
+}
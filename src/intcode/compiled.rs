@@ -0,0 +1,215 @@
+//! Runtime for programs compiled to Rust with `intcode_fn!`.
+//!
+//! The generated code runs until it meets something it can't handle (a
+//! write into its own code, a jump that wasn't compiled, anything which would
+//! be an error) and then hands its state over to a `Cpu`, which carries on
+//! from there for good.
+//!
+//! Arithmetic always wraps on overflow, as with `Arithmetic::Wrapping`, in
+//! the compiled code and the interpreter alike.
+
+use super::cpu::unwrap;
+use super::io::run_with;
+use super::memory::VEC_LIMIT;
+use super::{Arithmetic, Cpu, CpuError, CpuResult, Exit, SingleIO, IO};
+
+/// State shared with the generated code. Only public so the expansion can use it.
+#[doc(hidden)]
+pub struct Machine {
+    pub memory: Vec<i64>,
+    pub pc: usize,
+    pub rbo: i64,
+    /// Set once something writes over compiled code
+    pub stale: bool,
+    /// Words compiled into the code, apart from operands it patches itself
+    code: &'static [bool],
+}
+
+impl Machine {
+    /// Reads an operand which the program patches at runtime
+    #[inline(always)]
+    pub fn word(&self, addr: usize) -> i64 {
+        self.memory.get(addr).copied().unwrap_or(0)
+    }
+
+    #[inline(always)]
+    pub fn load(&self, addr: i64) -> Option<i64> {
+        if addr < 0 {
+            return None;
+        }
+        Some(self.word(addr as usize))
+    }
+
+    #[inline(always)]
+    pub fn load_rel(&self, offset: i64) -> Option<i64> {
+        self.load(self.rbo.checked_add(offset)?)
+    }
+
+    /// Writes to an address which isn't compiled code
+    #[inline(always)]
+    pub fn write(&mut self, addr: usize, value: i64) -> Option<()> {
+        if addr >= VEC_LIMIT {
+            return None;
+        }
+        if addr >= self.memory.len() {
+            self.memory.resize(addr + 1, 0);
+        }
+        self.memory[addr] = value;
+        Some(())
+    }
+
+    #[inline(always)]
+    pub fn store(&mut self, addr: i64, value: i64) -> Option<()> {
+        if addr < 0 {
+            return None;
+        }
+        let addr = addr as usize;
+        self.write(addr, value)?;
+        if self.code.get(addr) == Some(&true) {
+            self.stale = true;
+        }
+        Some(())
+    }
+
+    #[inline(always)]
+    pub fn store_rel(&mut self, offset: i64, value: i64) -> Option<()> {
+        self.store(self.rbo.checked_add(offset)?, value)
+    }
+
+    #[inline(always)]
+    pub fn jump(&mut self, target: i64) -> Option<()> {
+        if target < 0 {
+            return None;
+        }
+        self.pc = target as usize;
+        Some(())
+    }
+}
+
+/// A program compiled by `intcode_fn!`, used just like a `Cpu`
+pub struct Compiled {
+    machine: Machine,
+    /// Runs from `pc` until the next result, or `None` to hand over to `Cpu`
+    run: fn(&mut Machine) -> Option<CpuResult>,
+    /// Executes the input instruction at `pc`, or `None` if there isn't one
+    input: fn(&mut Machine, i64) -> Option<()>,
+    fallback: Option<Box<Cpu>>,
+}
+
+impl Compiled {
+    #[doc(hidden)]
+    pub fn new(
+        memory: &'static [i64],
+        code: &'static [bool],
+        run: fn(&mut Machine) -> Option<CpuResult>,
+        input: fn(&mut Machine, i64) -> Option<()>,
+    ) -> Self {
+        Compiled {
+            machine: Machine {
+                memory: memory.to_vec(),
+                pc: 0,
+                rbo: 0,
+                stale: false,
+                code,
+            },
+            run,
+            input,
+            fallback: None,
+        }
+    }
+
+    /// Whether execution has been handed over to the interpreter
+    pub fn is_interpreted(&self) -> bool {
+        self.fallback.is_some()
+    }
+
+    pub fn pc(&self) -> usize {
+        match &self.fallback {
+            Some(cpu) => cpu.pc,
+            None => self.machine.pc,
+        }
+    }
+
+    pub fn rbo(&self) -> i64 {
+        match &self.fallback {
+            Some(cpu) => cpu.rbo,
+            None => self.machine.rbo,
+        }
+    }
+
+    pub fn memory(&self) -> &[i64] {
+        match &self.fallback {
+            Some(cpu) => &cpu.memory,
+            None => &self.machine.memory,
+        }
+    }
+
+    /// Mutable access to memory. The compiled code can't know what changed,
+    /// so the interpreter takes over from here.
+    pub fn memory_mut(&mut self) -> &mut Vec<i64> {
+        &mut self.interpreter().memory
+    }
+
+    pub fn into_cpu(mut self) -> Cpu {
+        self.interpreter();
+        *self.fallback.unwrap()
+    }
+
+    pub fn run(&mut self, io: impl IO) -> Exit {
+        unwrap(self.try_run(io))
+    }
+
+    pub fn try_run(&mut self, io: impl IO) -> Result<Exit, CpuError> {
+        run_with(self, io, Self::try_resume, Self::try_input)
+    }
+
+    pub fn compute(&mut self, input: i64) -> i64 {
+        unwrap(self.try_compute(input))
+    }
+
+    pub fn try_compute(&mut self, input: i64) -> Result<i64, CpuError> {
+        let mut io = SingleIO::new(input);
+        let _ = self.try_run(&mut io)?;
+        Ok(io.output)
+    }
+
+    pub fn resume(&mut self) -> CpuResult {
+        unwrap(self.try_resume())
+    }
+
+    pub fn try_resume(&mut self) -> Result<CpuResult, CpuError> {
+        if self.fallback.is_none() {
+            if let Some(result) = (self.run)(&mut self.machine) {
+                return Ok(result);
+            }
+        }
+        self.interpreter().try_resume()
+    }
+
+    pub fn input(&mut self, input: i64) {
+        unwrap(self.try_input(input))
+    }
+
+    pub fn try_input(&mut self, input: i64) -> Result<(), CpuError> {
+        if self.fallback.is_none() {
+            if let Some(()) = (self.input)(&mut self.machine, input) {
+                if self.machine.stale {
+                    self.interpreter();
+                }
+                return Ok(());
+            }
+        }
+        self.interpreter().try_input(input)
+    }
+
+    fn interpreter(&mut self) -> &mut Cpu {
+        let machine = &mut self.machine;
+        self.fallback.get_or_insert_with(|| {
+            let mut cpu = Cpu::new(std::mem::take(&mut machine.memory));
+            cpu.pc = machine.pc;
+            cpu.rbo = machine.rbo;
+            cpu.arithmetic = Arithmetic::Wrapping;
+            Box::new(cpu)
+        })
+    }
+}
@@ -1,33 +1,65 @@
-use super::Cpu;
+use super::{Arithmetic, Cpu, CpuError, ErrorKind, Instruction, Memory, Operand, Word};
 
-impl Cpu {
-    pub(super) fn arg_get(&self, arg: usize) -> i64 {
-        let addr = self.arg_addr(false, arg);
-        if addr >= self.memory.len() {
-            assert!(addr < std::isize::MAX as usize, "overflow");
-            0
-        } else {
-            self.memory[addr]
+use smallvec::SmallVec;
+
+impl<W: Word, M: Memory<W>> Cpu<W, M> {
+    pub(super) fn arg_get(&self, arg: Operand<W>) -> Result<W, CpuError<W>> {
+        match arg {
+            Operand::Immediate(value) => Ok(value),
+            _ => Ok(self.read(self.arg_addr(arg)?)),
         }
     }
 
-    pub(super) fn arg_set(&mut self, arg: usize, value: i64) {
-        let addr = self.arg_addr(true, arg);
-        if addr >= self.memory.len() {
-            assert!(addr < std::isize::MAX as usize, "overflow");
-            self.memory.resize(addr + 1, 0);
+    pub(super) fn arg_set(&mut self, arg: Operand<W>, value: W) -> Result<(), CpuError<W>> {
+        let addr = self.arg_addr(arg)?;
+        if addr >= self.memory.limit() {
+            let addr = W::from_usize(addr).expect("addresses come from words");
+            return Err(self.error(ErrorKind::AddressOverflow(addr)));
         }
-        self.memory[addr] = value;
+        self.memory.write(addr, value);
+        Ok(())
+    }
+
+    pub(super) fn arg_addr(&self, arg: Operand<W>) -> Result<usize, CpuError<W>> {
+        match arg {
+            Operand::Position(addr) => self.address(addr),
+            Operand::Relative(offset) => match self.rbo.add_with(&offset, Arithmetic::Checked) {
+                Some(addr) => self.address(addr),
+                // Wrapping around could land on any address at all
+                None => Err(self.error(ErrorKind::Overflow)),
+            },
+            // Only reachable for loads, stores never decode to immediates
+            Operand::Immediate(_) => unreachable!("immediate operands have no address"),
+        }
+    }
+
+    pub(super) fn address(&self, addr: W) -> Result<usize, CpuError<W>> {
+        match addr.to_usize() {
+            Some(addr) => Ok(addr),
+            None if addr < W::zero() => Err(self.error(ErrorKind::NegativeAddress(addr))),
+            None => Err(self.error(ErrorKind::AddressOverflow(addr))),
+        }
+    }
+
+    pub(super) fn read(&self, addr: usize) -> W {
+        self.memory.read(addr)
     }
 
-    pub(super) fn arg_addr(&self, store: bool, arg: usize) -> usize {
-        let instr = self.memory[self.pc];
-        let mode = (instr / 10i64.pow(arg as u32 + 1)) % 10;
-        match mode & !(store as i64) {
-            0 => self.memory[self.pc + arg] as usize,
-            1 => self.pc + arg,
-            2 => (self.rbo + self.memory[self.pc + arg]) as usize,
-            _ => panic!("Unknown addressing mode {}", mode),
+    /// The operands `instruction` will actually load or store, which leaves
+    /// out the target of a jump that isn't taken
+    pub(super) fn used_operands(
+        &self,
+        instruction: &Instruction<W>,
+    ) -> Result<SmallVec<[Operand<W>; 3]>, CpuError<W>> {
+        let mut operands = instruction.operands();
+        let taken = match instruction {
+            Instruction::Jnz(cond, _) => self.arg_get(cond.clone())? != W::zero(),
+            Instruction::Jz(cond, _) => self.arg_get(cond.clone())? == W::zero(),
+            _ => true,
+        };
+        if !taken {
+            operands.truncate(1);
         }
+        Ok(operands)
     }
 }
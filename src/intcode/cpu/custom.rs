@@ -0,0 +1,197 @@
+use super::decode::{digits, mode};
+use super::{arity, Cpu, CpuError, ErrorKind, Instruction, Memory, Operand, Word};
+
+use std::cell::RefCell;
+
+type Handler<W, M> = Box<dyn FnMut(&mut Extension<W, M>) -> Result<(), CpuError<W>> + Send>;
+
+pub(super) struct CustomOp<W, M> {
+    arity: usize,
+    handler: Handler<W, M>,
+}
+
+/// Memory a custom opcode handler used through its `Extension`
+#[derive(Clone)]
+pub(super) enum MemoryAccess<W> {
+    Read(usize, W),
+    Write { addr: usize, old: W, new: W },
+}
+
+/// What a custom opcode handler sees of the instruction being run
+pub struct Extension<'a, W = i64, M = Vec<W>> {
+    cpu: &'a mut Cpu<W, M>,
+    pc: usize,
+    instr: i64,
+    arity: usize,
+    jump: Option<usize>,
+    /// Filled in if the cpu is collecting accesses
+    accesses: Option<RefCell<Vec<MemoryAccess<W>>>>,
+}
+
+impl<W: Word, M: Memory<W>> Cpu<W, M> {
+    /// Runs `handler` for every instruction with an opcode the cpu doesn't
+    /// know. The instruction takes `arity` operands, which the handler
+    /// decodes with `Extension::get` and `Extension::set`.
+    ///
+    /// # Panics
+    ///
+    /// If `opcode` is one of the built in instructions or outside `1..100`,
+    /// or `arity` is more than 3.
+    pub fn register_opcode(
+        &mut self,
+        opcode: i64,
+        arity: usize,
+        handler: impl FnMut(&mut Extension<W, M>) -> Result<(), CpuError<W>> + Send + 'static,
+    ) {
+        assert!(
+            (1..100).contains(&opcode) && self::arity(opcode).is_none(),
+            "opcode {} is not free",
+            opcode
+        );
+        assert!(arity <= 3, "instructions have at most 3 operands");
+        let handler = Box::new(handler);
+        self.opcodes.insert(opcode, CustomOp { arity, handler });
+    }
+
+    pub fn unregister_opcode(&mut self, opcode: i64) {
+        self.opcodes.remove(&opcode);
+    }
+
+    /// Turns an unknown opcode into `Instruction::Custom` if it's registered
+    #[cold]
+    pub(super) fn decode_custom(&self, err: CpuError<W>) -> Result<Instruction<W>, CpuError<W>> {
+        match err.kind {
+            ErrorKind::UnknownOpcode(opcode) => match self.opcodes.get(&opcode) {
+                Some(op) => Ok(Instruction::Custom {
+                    opcode,
+                    arity: op.arity,
+                }),
+                None => Err(err),
+            },
+            _ => Err(err),
+        }
+    }
+
+    /// Runs `f`, returning what custom opcodes read and wrote meanwhile.
+    /// Anyone already watching still sees the accesses too.
+    pub(super) fn watched<T>(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> T,
+    ) -> (T, Vec<MemoryAccess<W>>) {
+        let outer = self.accesses.replace(Vec::new());
+        let result = f(self);
+        let accesses = self.accesses.take().unwrap_or_default();
+        if let Some(mut outer) = outer {
+            outer.extend(accesses.iter().cloned());
+            self.accesses = Some(outer);
+        }
+        (result, accesses)
+    }
+
+    #[cold]
+    pub(super) fn i_custom(&mut self, opcode: i64, arity: usize) -> Result<(), CpuError<W>> {
+        // Taken out so the handler can borrow the cpu
+        let mut op = match self.opcodes.remove(&opcode) {
+            Some(op) => op,
+            None => return Err(self.error(ErrorKind::UnknownOpcode(opcode))),
+        };
+        let pc = self.pc;
+        let instr = digits(&self.read(pc));
+        let accesses = self.accesses.as_ref().map(|_| RefCell::default());
+        let mut extension = Extension {
+            cpu: self,
+            pc,
+            instr,
+            arity,
+            jump: None,
+            accesses,
+        };
+        let result = (op.handler)(&mut extension);
+        let (jump, log) = (extension.jump, extension.accesses);
+        if let (Some(accesses), Some(log)) = (&mut self.accesses, log) {
+            accesses.extend(log.into_inner());
+        }
+        self.opcodes.entry(opcode).or_insert(op);
+        result?;
+        self.pc = jump.unwrap_or(pc + arity + 1);
+        Ok(())
+    }
+}
+
+impl<W: Word, M: Memory<W>> Extension<'_, W, M> {
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn opcode(&self) -> i64 {
+        self.instr % 100
+    }
+
+    /// Operand `arg`, counting from 1 like the modes in the instruction
+    pub fn operand(&self, arg: usize) -> Result<Operand<W>, CpuError<W>> {
+        self.check_arg(arg);
+        let mode = mode(self.instr, arg);
+        Operand::with_mode(mode, self.cpu.read(self.pc + arg))
+            .ok_or_else(|| self.error(ErrorKind::UnknownMode { arg, mode }))
+    }
+
+    /// Loads the value of operand `arg`
+    pub fn get(&self, arg: usize) -> Result<W, CpuError<W>> {
+        let operand = self.operand(arg)?;
+        let value = self.cpu.arg_get(operand.clone())?;
+        if self.accesses.is_some() && !matches!(operand, Operand::Immediate(_)) {
+            let addr = self.cpu.arg_addr(operand)?;
+            self.record(MemoryAccess::Read(addr, value.clone()));
+        }
+        Ok(value)
+    }
+
+    /// Stores to operand `arg`. Immediate mode is treated as position mode
+    /// and mode 3 as relative, as they are for the built in instructions.
+    pub fn set(&mut self, arg: usize, value: W) -> Result<(), CpuError<W>> {
+        self.check_arg(arg);
+        let mode = mode(self.instr, arg);
+        let operand = Operand::with_mode(mode & !1, self.cpu.read(self.pc + arg))
+            .ok_or_else(|| self.error(ErrorKind::UnknownMode { arg, mode }))?;
+        if self.accesses.is_none() {
+            return self.cpu.arg_set(operand, value);
+        }
+        let addr = self.cpu.arg_addr(operand.clone())?;
+        let old = self.cpu.read(addr);
+        self.cpu.arg_set(operand, value)?;
+        let new = self.cpu.read(addr);
+        self.record(MemoryAccess::Write { addr, old, new });
+        Ok(())
+    }
+
+    /// Continues at `target` instead of the next instruction
+    pub fn jump(&mut self, target: W) -> Result<(), CpuError<W>> {
+        self.jump = Some(self.cpu.address(target)?);
+        Ok(())
+    }
+
+    /// The cpu running the instruction. Its `pc` is overwritten once the
+    /// handler returns, use `jump` to move it.
+    pub fn cpu(&mut self) -> &mut Cpu<W, M> {
+        self.cpu
+    }
+
+    fn record(&self, access: MemoryAccess<W>) {
+        if let Some(accesses) = &self.accesses {
+            accesses.borrow_mut().push(access);
+        }
+    }
+
+    fn check_arg(&self, arg: usize) {
+        assert!(
+            (1..=self.arity).contains(&arg),
+            "operand {} out of range",
+            arg
+        );
+    }
+
+    /// An error at the instruction being run
+    pub fn error(&self, kind: ErrorKind<W>) -> CpuError<W> {
+        self.cpu.error(kind)
+    }
+}
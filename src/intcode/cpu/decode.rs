@@ -0,0 +1,225 @@
+use super::{CpuError, ErrorKind};
+use crate::intcode::Word;
+
+use std::fmt;
+
+use smallvec::SmallVec;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Operand<W = i64> {
+    Position(W),
+    Immediate(W),
+    Relative(W),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Instruction<W = i64> {
+    Add(Operand<W>, Operand<W>, Operand<W>),
+    Mul(Operand<W>, Operand<W>, Operand<W>),
+    In(Operand<W>),
+    Out(Operand<W>),
+    Jnz(Operand<W>, Operand<W>),
+    Jz(Operand<W>, Operand<W>),
+    Lt(Operand<W>, Operand<W>, Operand<W>),
+    Eq(Operand<W>, Operand<W>, Operand<W>),
+    Arbo(Operand<W>),
+    Halt,
+    /// An opcode registered with `Cpu::register_opcode`. Its operands are
+    /// decoded by the handler.
+    Custom {
+        opcode: i64,
+        arity: usize,
+    },
+}
+
+impl<W> Operand<W> {
+    #[inline(always)]
+    pub(super) fn with_mode(mode: i64, value: W) -> Option<Self> {
+        match mode {
+            0 => Some(Operand::Position(value)),
+            1 => Some(Operand::Immediate(value)),
+            2 => Some(Operand::Relative(value)),
+            _ => None,
+        }
+    }
+}
+
+impl<W: Clone> Instruction<W> {
+    pub fn opcode(&self) -> i64 {
+        match self {
+            Instruction::Add(..) => 1,
+            Instruction::Mul(..) => 2,
+            Instruction::In(..) => 3,
+            Instruction::Out(..) => 4,
+            Instruction::Jnz(..) => 5,
+            Instruction::Jz(..) => 6,
+            Instruction::Lt(..) => 7,
+            Instruction::Eq(..) => 8,
+            Instruction::Arbo(..) => 9,
+            Instruction::Halt => 99,
+            Instruction::Custom { opcode, .. } => *opcode,
+        }
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Instruction::Add(..) => "add",
+            Instruction::Mul(..) => "mul",
+            Instruction::In(..) => "in",
+            Instruction::Out(..) => "out",
+            Instruction::Jnz(..) => "jnz",
+            Instruction::Jz(..) => "jz",
+            Instruction::Lt(..) => "lt",
+            Instruction::Eq(..) => "eq",
+            Instruction::Arbo(..) => "arb",
+            Instruction::Halt => "hlt",
+            Instruction::Custom { .. } => "op",
+        }
+    }
+
+    pub fn operands(&self) -> SmallVec<[Operand<W>; 3]> {
+        let ops: SmallVec<[&Operand<W>; 3]> = match self {
+            Instruction::Add(a, b, c)
+            | Instruction::Mul(a, b, c)
+            | Instruction::Lt(a, b, c)
+            | Instruction::Eq(a, b, c) => SmallVec::from_buf([a, b, c]),
+            Instruction::Jnz(a, b) | Instruction::Jz(a, b) => SmallVec::from_slice(&[a, b]),
+            Instruction::In(a) | Instruction::Out(a) | Instruction::Arbo(a) => {
+                SmallVec::from_slice(&[a])
+            }
+            Instruction::Halt | Instruction::Custom { .. } => SmallVec::new(),
+        };
+        ops.into_iter().cloned().collect()
+    }
+
+    /// The operand written to, if the instruction writes to memory
+    pub fn destination(&self) -> Option<Operand<W>> {
+        match self {
+            Instruction::Add(_, _, out)
+            | Instruction::Mul(_, _, out)
+            | Instruction::Lt(_, _, out)
+            | Instruction::Eq(_, _, out)
+            | Instruction::In(out) => Some(out.clone()),
+            _ => None,
+        }
+    }
+
+    /// Number of memory words the instruction occupies, including the opcode
+    pub fn size(&self) -> usize {
+        match self {
+            Instruction::Custom { arity, .. } => arity + 1,
+            _ => arity(self.opcode()).unwrap_or(0) + 1,
+        }
+    }
+}
+
+impl<W: fmt::Display> fmt::Display for Operand<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::Position(addr) => write!(f, "{}", addr),
+            Operand::Immediate(value) => write!(f, "#{}", value),
+            Operand::Relative(offset) => write!(f, "@{}", offset),
+        }
+    }
+}
+
+impl<W: Clone + fmt::Display> fmt::Display for Instruction<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.mnemonic())?;
+        if let Instruction::Custom { opcode, .. } = self {
+            write!(f, "{}", opcode)?;
+        }
+        for (i, op) in self.operands().iter().enumerate() {
+            let sep = if i == 0 { " " } else { ", " };
+            write!(f, "{}{}", sep, op)?;
+        }
+        Ok(())
+    }
+}
+
+/// Number of operands taken by `opcode`, or `None` if it isn't a known opcode
+pub fn arity(opcode: i64) -> Option<usize> {
+    match opcode {
+        1 | 2 | 7 | 8 => Some(3),
+        5 | 6 => Some(2),
+        3 | 4 | 9 => Some(1),
+        99 => Some(0),
+        _ => None,
+    }
+}
+
+/// Opcode for a mnemonic as returned by `Instruction::mnemonic`
+pub fn opcode(mnemonic: &str) -> Option<i64> {
+    Some(match mnemonic {
+        "add" => 1,
+        "mul" => 2,
+        "in" => 3,
+        "out" => 4,
+        "jnz" => 5,
+        "jz" => 6,
+        "lt" => 7,
+        "eq" => 8,
+        "arb" => 9,
+        "hlt" => 99,
+        _ => return None,
+    })
+}
+
+pub fn decode<W: Word>(memory: &[W], pc: usize) -> Result<Instruction<W>, CpuError<W>> {
+    decode_with(|addr| memory.get(addr).cloned().unwrap_or_else(W::zero), pc)
+}
+
+/// Decodes the instruction at `pc`, reading memory through `read`
+#[inline(always)]
+pub fn decode_with<W: Word>(
+    read: impl Fn(usize) -> W,
+    pc: usize,
+) -> Result<Instruction<W>, CpuError<W>> {
+    let word = read(pc);
+    let instr = digits(&word);
+    let error = |kind| CpuError {
+        pc,
+        instruction: word.clone(),
+        kind,
+    };
+
+    let operand = |arg: usize, store: bool| {
+        let mode = mode(instr, arg);
+        Operand::with_mode(mode & !(store as i64), read(pc + arg))
+            .ok_or_else(|| error(ErrorKind::UnknownMode { arg, mode }))
+    };
+    let load = |arg| operand(arg, false);
+    let store = |arg| operand(arg, true);
+
+    Ok(match instr % 100 {
+        1 => Instruction::Add(load(1)?, load(2)?, store(3)?),
+        2 => Instruction::Mul(load(1)?, load(2)?, store(3)?),
+        3 => Instruction::In(store(1)?),
+        4 => Instruction::Out(load(1)?),
+        5 => Instruction::Jnz(load(1)?, load(2)?),
+        6 => Instruction::Jz(load(1)?, load(2)?),
+        7 => Instruction::Lt(load(1)?, load(2)?, store(3)?),
+        8 => Instruction::Eq(load(1)?, load(2)?, store(3)?),
+        9 => Instruction::Arbo(load(1)?),
+        99 => Instruction::Halt,
+        op => return Err(error(ErrorKind::UnknownOpcode(op))),
+    })
+}
+
+/// The opcode and modes of an instruction word. Digits past the modes of
+/// the last operand never matter.
+#[inline(always)]
+pub(super) fn digits<W: Word>(word: &W) -> i64 {
+    match word.to_i64() {
+        Some(instr) => instr,
+        None => (word.clone() % W::from_i64(100_000).unwrap())
+            .to_i64()
+            .unwrap(),
+    }
+}
+
+/// Mode digit of operand `arg`, counting from 1
+#[inline(always)]
+pub(super) fn mode(instr: i64, arg: usize) -> i64 {
+    (instr / 10i64.pow(arg as u32 + 1)) % 10
+}
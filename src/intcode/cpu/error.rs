@@ -0,0 +1,68 @@
+use crate::intcode::Word;
+
+use std::error::Error;
+use std::fmt;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CpuError<W = i64> {
+    pub pc: usize,
+    pub instruction: W,
+    pub kind: ErrorKind<W>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ErrorKind<W = i64> {
+    UnknownOpcode(i64),
+    UnknownMode {
+        arg: usize,
+        mode: i64,
+    },
+    NegativeAddress(W),
+    /// An address too large to index memory with, or to write to. `Vec`
+    /// memory stops at `VEC_LIMIT`, `PagedMemory` takes any address.
+    AddressOverflow(W),
+    /// Arithmetic overflowed under `Arithmetic::Checked`, or while computing
+    /// a relative address
+    Overflow,
+    UnexpectedInput,
+    /// The program wanted input after all the inputs given were used up
+    OutOfInput,
+    /// Loop detection found the program stuck in a loop
+    InfiniteLoop {
+        start: usize,
+        length: u64,
+    },
+}
+
+impl<W: fmt::Display> fmt::Display for CpuError<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} (pc = {}, instruction = {})",
+            self.kind, self.pc, self.instruction
+        )
+    }
+}
+
+impl<W: fmt::Display> fmt::Display for ErrorKind<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorKind::UnknownOpcode(op) => write!(f, "Unknown opcode {}", op),
+            ErrorKind::UnknownMode { arg, mode } => {
+                write!(f, "Unknown addressing mode {} for argument {}", mode, arg)
+            }
+            ErrorKind::NegativeAddress(addr) => write!(f, "Negative address {}", addr),
+            ErrorKind::AddressOverflow(addr) => write!(f, "Address {} is too large", addr),
+            ErrorKind::Overflow => write!(f, "Arithmetic overflow"),
+            ErrorKind::UnexpectedInput => write!(f, "Input given while not waiting for input"),
+            ErrorKind::OutOfInput => write!(f, "Waiting for input after all of it was used"),
+            ErrorKind::InfiniteLoop { start, length } => write!(
+                f,
+                "Stuck in a loop of {} instructions starting at {}",
+                length, start
+            ),
+        }
+    }
+}
+
+impl<W: Word> Error for CpuError<W> {}
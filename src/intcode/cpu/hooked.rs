@@ -0,0 +1,97 @@
+use super::custom::MemoryAccess;
+use super::{Cpu, CpuError, CpuResult, Instruction, Memory, Operand, Word};
+use crate::intcode::hooks::Hook;
+
+use smallvec::SmallVec;
+
+impl<W: Word, M: Memory<W>> Cpu<W, M> {
+    /// Calls `hook` for every event from now on, after any hooks added before
+    pub fn add_hook(&mut self, hook: impl Hook<W> + Send + 'static) {
+        self.hooks.push(Box::new(hook));
+    }
+
+    pub fn clear_hooks(&mut self) {
+        self.hooks.clear();
+    }
+
+    #[cold]
+    pub(super) fn hooked(
+        &mut self,
+        instruction: Instruction<W>,
+        input: Option<W>,
+    ) -> Result<Option<CpuResult<W>>, CpuError<W>> {
+        if let (Instruction::In(_), None) = (&instruction, &input) {
+            return Ok(Some(CpuResult::Input));
+        }
+
+        let pc = self.pc;
+        let rbo = self.rbo.clone();
+        let operands = self.used_operands(&instruction)?;
+        let dest = instruction.destination();
+        let loads = &operands[..operands.len() - dest.is_some() as usize];
+        // Addresses that don't resolve are left for `dispatch` to report, so
+        // the error is the same one an unhooked cpu gives
+        let mut reads = SmallVec::<[(usize, W); 3]>::new();
+        for arg in loads.iter().cloned() {
+            if let Operand::Immediate(_) = arg {
+                continue;
+            }
+            if let Ok(addr) = self.arg_addr(arg) {
+                reads.push((addr, self.read(addr)));
+            }
+        }
+        let write = dest
+            .and_then(|arg| self.arg_addr(arg).ok())
+            .map(|addr| (addr, self.read(addr)));
+        let taken = match &instruction {
+            Instruction::Jnz(..) | Instruction::Jz(..) => operands.len() == 2,
+            _ => false,
+        };
+        let arbo = matches!(instruction, Instruction::Arbo(_));
+
+        // Custom opcodes go through their `Extension` instead of operands
+        let (result, accesses) = match instruction {
+            Instruction::Custom { .. } => {
+                self.watched(|cpu| cpu.dispatch(instruction, input.clone()))
+            }
+            _ => (self.dispatch(instruction, input.clone()), Vec::new()),
+        };
+        let result = result?;
+
+        // Taken out so the hooks can be borrowed alongside memory
+        let mut hooks = std::mem::take(&mut self.hooks);
+        for hook in &mut hooks {
+            for (addr, value) in &reads {
+                hook.read(*addr, value.clone());
+            }
+            for access in &accesses {
+                match access {
+                    MemoryAccess::Read(addr, value) => hook.read(*addr, value.clone()),
+                    MemoryAccess::Write { addr, old, new } => {
+                        hook.write(*addr, old.clone(), new.clone())
+                    }
+                }
+            }
+            if let Some(value) = &input {
+                hook.input(value.clone());
+            }
+            if let Some((addr, old)) = &write {
+                hook.write(*addr, old.clone(), self.read(*addr));
+            }
+            if taken {
+                hook.jump(pc, self.pc);
+            }
+            if arbo {
+                hook.rbo(rbo.clone(), self.rbo.clone());
+            }
+            match &result {
+                Some(CpuResult::Output(value)) => hook.output(value.clone()),
+                Some(CpuResult::Halt) => hook.halt(pc),
+                _ => (),
+            }
+        }
+        self.hooks = hooks;
+
+        Ok(result)
+    }
+}
@@ -1,64 +1,103 @@
-use super::Cpu;
+use super::{Cpu, CpuError, ErrorKind, Memory, Operand, Word};
 
-impl Cpu {
-    pub(super) fn i_add(&mut self) {
-        let a = self.arg_get(1);
-        let b = self.arg_get(2);
-        self.arg_set(3, a + b);
+impl<W: Word, M: Memory<W>> Cpu<W, M> {
+    pub(super) fn i_add(
+        &mut self,
+        a: Operand<W>,
+        b: Operand<W>,
+        out: Operand<W>,
+    ) -> Result<(), CpuError<W>> {
+        let a = self.arg_get(a)?;
+        let b = self.arg_get(b)?;
+        let sum = a.add_with(&b, self.arithmetic);
+        self.arg_set(out, self.overflow(sum)?)?;
         self.pc += 4;
+        Ok(())
     }
 
-    pub(super) fn i_mul(&mut self) {
-        let a = self.arg_get(1);
-        let b = self.arg_get(2);
-        self.arg_set(3, a * b);
+    pub(super) fn i_mul(
+        &mut self,
+        a: Operand<W>,
+        b: Operand<W>,
+        out: Operand<W>,
+    ) -> Result<(), CpuError<W>> {
+        let a = self.arg_get(a)?;
+        let b = self.arg_get(b)?;
+        let product = a.mul_with(&b, self.arithmetic);
+        self.arg_set(out, self.overflow(product)?)?;
         self.pc += 4;
+        Ok(())
     }
 
-    pub(super) fn i_in(&mut self, input: i64) {
-        self.arg_set(1, input);
+    pub(super) fn i_in(&mut self, out: Operand<W>, input: W) -> Result<(), CpuError<W>> {
+        self.arg_set(out, input)?;
         self.pc += 2;
+        Ok(())
     }
 
-    pub(super) fn i_out(&mut self) -> i64 {
-        let val = self.arg_get(1);
+    pub(super) fn i_out(&mut self, a: Operand<W>) -> Result<W, CpuError<W>> {
+        let val = self.arg_get(a)?;
         self.pc += 2;
-        val
+        Ok(val)
     }
 
-    pub(super) fn i_jnz(&mut self) {
-        if self.arg_get(1) != 0 {
-            self.pc = self.arg_get(2) as usize;
+    pub(super) fn i_jnz(
+        &mut self,
+        cond: Operand<W>,
+        target: Operand<W>,
+    ) -> Result<(), CpuError<W>> {
+        if self.arg_get(cond)? != W::zero() {
+            self.pc = self.address(self.arg_get(target)?)?;
         } else {
             self.pc += 3;
         }
+        Ok(())
     }
 
-    pub(super) fn i_jz(&mut self) {
-        if self.arg_get(1) == 0 {
-            self.pc = self.arg_get(2) as usize;
+    pub(super) fn i_jz(&mut self, cond: Operand<W>, target: Operand<W>) -> Result<(), CpuError<W>> {
+        if self.arg_get(cond)? == W::zero() {
+            self.pc = self.address(self.arg_get(target)?)?;
         } else {
             self.pc += 3;
         }
+        Ok(())
     }
 
-    pub(super) fn i_lt(&mut self) {
-        let cond = self.arg_get(1) < self.arg_get(2);
-        let value = cond as i64;
-        self.arg_set(3, value);
+    pub(super) fn i_lt(
+        &mut self,
+        a: Operand<W>,
+        b: Operand<W>,
+        out: Operand<W>,
+    ) -> Result<(), CpuError<W>> {
+        let cond = self.arg_get(a)? < self.arg_get(b)?;
+        let value = if cond { W::one() } else { W::zero() };
+        self.arg_set(out, value)?;
         self.pc += 4;
+        Ok(())
     }
 
-    pub(super) fn i_eq(&mut self) {
-        let cond = self.arg_get(1) == self.arg_get(2);
-        let value = cond as i64;
-        self.arg_set(3, value);
+    pub(super) fn i_eq(
+        &mut self,
+        a: Operand<W>,
+        b: Operand<W>,
+        out: Operand<W>,
+    ) -> Result<(), CpuError<W>> {
+        let cond = self.arg_get(a)? == self.arg_get(b)?;
+        let value = if cond { W::one() } else { W::zero() };
+        self.arg_set(out, value)?;
         self.pc += 4;
+        Ok(())
     }
 
-    pub(super) fn i_arbo(&mut self) {
-        let value = self.arg_get(1);
-        self.rbo += value;
+    pub(super) fn i_arbo(&mut self, a: Operand<W>) -> Result<(), CpuError<W>> {
+        let value = self.arg_get(a)?;
+        let rbo = self.rbo.add_with(&value, self.arithmetic);
+        self.rbo = self.overflow(rbo)?;
         self.pc += 2;
+        Ok(())
+    }
+
+    fn overflow(&self, result: Option<W>) -> Result<W, CpuError<W>> {
+        result.ok_or_else(|| self.error(ErrorKind::Overflow))
     }
 }
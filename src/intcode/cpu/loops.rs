@@ -0,0 +1,124 @@
+use super::{Cpu, CpuError, CpuResult, ErrorKind, Memory, Word};
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+/// Fingerprints kept before starting over, so a long run without I/O can't
+/// use up memory. Loops longer than this many intervals go unnoticed.
+const MAX_SEEN: usize = 1 << 16;
+
+/// Fingerprints of the states seen since the last I/O. Without I/O a program
+/// is deterministic, so seeing the same state twice means it will never get
+/// out of the loop between them.
+pub(super) struct LoopDetector<W> {
+    interval: u64,
+    /// Instructions since the last I/O
+    count: u64,
+    /// When each fingerprint was taken
+    seen: HashMap<u64, u64>,
+    /// Going once around a loop that looks like it repeats
+    measuring: Option<Measure<W>>,
+}
+
+/// The state a suspected loop started from, and what's been seen since
+struct Measure<W> {
+    fingerprint: u64,
+    pc: usize,
+    rbo: W,
+    start: usize,
+    length: u64,
+    /// A real loop gets back within the instructions between the two
+    /// matching fingerprints, anything longer was a collision
+    limit: u64,
+}
+
+impl<W: Word, M: Memory<W>> Cpu<W, M> {
+    /// Fingerprints the state every `interval` instructions, failing with
+    /// `ErrorKind::InfiniteLoop` once one repeats without any I/O in between.
+    ///
+    /// A loop is reported as an error, so `run`, `resume` and the other
+    /// methods without a `try_` prefix panic when one is found. Use
+    /// `try_run`, `try_resume` or `try_resume_for` to get it back as a
+    /// `CpuError` instead.
+    pub fn start_loop_detection(&mut self, interval: u64) {
+        assert!(interval > 0, "loop detection needs a non-zero interval");
+        self.loops = Some(Box::new(LoopDetector {
+            interval,
+            count: 0,
+            seen: HashMap::new(),
+            measuring: None,
+        }));
+    }
+
+    pub fn stop_loop_detection(&mut self) {
+        self.loops = None;
+    }
+
+    /// Called after each instruction run by `step`
+    pub(super) fn check_loop(&mut self, result: &Option<CpuResult<W>>) -> Result<(), CpuError<W>> {
+        let detector = match &mut self.loops {
+            Some(detector) => detector,
+            None => return Ok(()),
+        };
+        if result.is_some() {
+            detector.count = 0;
+            detector.seen.clear();
+            detector.measuring = None;
+            return Ok(());
+        }
+        detector.count += 1;
+
+        // Measures the loop one instruction at a time, so it's found the same
+        // way whether or not the caller has a budget
+        if let Some(measure) = &mut detector.measuring {
+            measure.length += 1;
+            measure.start = measure.start.min(self.pc);
+            // Only hash memory when it could possibly match
+            if self.pc == measure.pc && self.rbo == measure.rbo {
+                let (fingerprint, start, length) =
+                    (measure.fingerprint, measure.start, measure.length);
+                if self.fingerprint() == fingerprint {
+                    // Carrying on measures the loop again from scratch
+                    self.loops.as_mut().unwrap().measuring = None;
+                    return Err(self.error(ErrorKind::InfiniteLoop { start, length }));
+                }
+            }
+            let detector = self.loops.as_mut().unwrap();
+            let measure = detector.measuring.as_ref().unwrap();
+            if measure.length >= measure.limit {
+                detector.measuring = None;
+            }
+            return Ok(());
+        }
+
+        if detector.count % detector.interval != 0 {
+            return Ok(());
+        }
+        let count = detector.count;
+        let fingerprint = self.fingerprint();
+        let detector = self.loops.as_mut().unwrap();
+        if detector.seen.len() >= MAX_SEEN {
+            detector.seen.clear();
+        }
+        if let Some(seen) = detector.seen.insert(fingerprint, count) {
+            detector.measuring = Some(Measure {
+                fingerprint,
+                pc: self.pc,
+                rbo: self.rbo.clone(),
+                start: self.pc,
+                length: 0,
+                limit: count - seen,
+            });
+        }
+        Ok(())
+    }
+
+    fn fingerprint(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.pc.hash(&mut hasher);
+        self.rbo.hash(&mut hasher);
+        self.memory.hash_words(&mut hasher);
+        hasher.finish()
+    }
+}
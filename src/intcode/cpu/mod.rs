@@ -1,75 +1,419 @@
+use super::asyncio::AsyncIO;
+use super::hooks::Hook;
+use super::io::run_with;
+use super::memory::Memory;
 use super::parse;
-use super::IO;
+use super::profile::Profile;
+use super::trace::Tracer;
 use super::SingleIO;
+use super::{Arithmetic, Word};
+use super::{Exit, Flow, Input, IO};
+
+use self::custom::{CustomOp, MemoryAccess};
+use self::loops::LoopDetector;
+
+use std::collections::HashMap;
+use std::future;
+use std::task::Poll;
+
+pub use self::custom::Extension;
+pub use self::decode::{arity, decode, decode_with, opcode, Instruction, Operand};
+pub use self::error::{CpuError, ErrorKind};
+pub use self::outputs::Outputs;
 
 mod addressing;
+mod custom;
+mod decode;
+mod error;
+mod hooked;
 mod instructions;
+mod loops;
+mod outputs;
+mod profiling;
+mod save;
+mod tracing;
 
-pub struct Cpu {
-    pub memory: Vec<i64>,
+pub struct Cpu<W = i64, M = Vec<W>> {
+    pub memory: M,
     pub pc: usize,
-    pub rbo: i64,
+    pub rbo: W,
+    /// How `add`, `mul` and relative base adjustments handle overflow
+    pub arithmetic: Arithmetic,
+    tracer: Option<Tracer>,
+    profile: Option<Box<Profile>>,
+    hooks: Vec<Box<dyn Hook<W> + Send>>,
+    loops: Option<Box<LoopDetector<W>>>,
+    opcodes: HashMap<i64, CustomOp<W, M>>,
+    /// What custom opcodes read and wrote, collected while something is watching
+    accesses: Option<Vec<MemoryAccess<W>>>,
 }
 
 impl Cpu {
+    /// A cpu backed by a `Vec`, which can't grow past `memory::VEC_LIMIT`
+    /// words. Writing past that is an `ErrorKind::AddressOverflow`, use
+    /// `with_memory` and `PagedMemory` for programs that need more.
     pub fn new(memory: Vec<i64>) -> Self {
+        Cpu::with_memory(memory)
+    }
+
+    pub fn parse(input: &str) -> Self {
+        let memory = parse(input);
+        Cpu::new(memory)
+    }
+}
+
+impl<W: Word, M: Memory<W>> Cpu<W, M> {
+    /// A cpu with any word type or memory backend. Writes at or past the
+    /// backend's `Memory::limit` fail with `ErrorKind::AddressOverflow`.
+    pub fn with_memory(memory: M) -> Self {
         Cpu {
             memory,
             pc: 0,
-            rbo: 0,
+            rbo: W::zero(),
+            arithmetic: Arithmetic::default(),
+            tracer: None,
+            profile: None,
+            hooks: Vec::new(),
+            loops: None,
+            opcodes: HashMap::new(),
+            accesses: None,
         }
     }
 }
 
-impl Cpu {
-    pub fn parse(input: &str) -> Self {
-        let memory = parse(input);
-        Cpu::new(memory)
+impl<W: Word, M: Memory<W>> Cpu<W, M> {
+    pub fn run(&mut self, io: impl IO<W>) -> Exit {
+        unwrap(self.try_run(io))
+    }
+
+    /// Runs until the program halts or `io` pauses or stops it. Pausing
+    /// while waiting for input leaves the cpu waiting, so running again
+    /// asks for the input again.
+    pub fn try_run(&mut self, io: impl IO<W>) -> Result<Exit, CpuError<W>> {
+        // `io` can't attach anything, so checking once keeps the check out
+        // of every resume
+        if self.needs_step() {
+            run_with(self, io, Self::resume_stepped, Self::input_stepped)
+        } else {
+            run_with(self, io, Self::resume_plain, Self::input_plain)
+        }
     }
 
-    pub fn run(&mut self, mut io: impl IO) {
+    pub async fn run_async(&mut self, io: impl AsyncIO<W>) -> Exit {
+        unwrap(self.try_run_async(io).await)
+    }
+
+    /// Like `try_run`, but waits on `io` instead of blocking. Long stretches
+    /// without I/O yield every so often so other tasks get a turn.
+    pub async fn try_run_async(&mut self, mut io: impl AsyncIO<W>) -> Result<Exit, CpuError<W>> {
         loop {
-            match self.resume() {
-                CpuResult::Halt => break,
-                CpuResult::Input => self.input(io.input()),
-                CpuResult::Output(out) => io.output(out),
+            match self.try_resume_for(ASYNC_BUDGET)? {
+                Budget::Done(CpuResult::Halt) => break Ok(Exit::Halt),
+                Budget::Done(CpuResult::Input) => match io.input().await {
+                    Input::Value(value) => self.try_input(value)?,
+                    Input::Pause => break Ok(Exit::Pause),
+                    Input::Stop => break Ok(Exit::Stop),
+                },
+                Budget::Done(CpuResult::Output(out)) => match io.output(out).await {
+                    Flow::Continue => (),
+                    Flow::Pause => break Ok(Exit::Pause),
+                    Flow::Stop => break Ok(Exit::Stop),
+                },
+                Budget::Yield => yield_now().await,
             }
         }
     }
 
-    pub fn resume(&mut self) -> CpuResult {
+    pub fn resume(&mut self) -> CpuResult<W> {
+        unwrap(self.try_resume())
+    }
+
+    pub fn try_resume(&mut self) -> Result<CpuResult<W>, CpuError<W>> {
+        if self.needs_step() {
+            self.resume_stepped()
+        } else {
+            self.resume_plain()
+        }
+    }
+
+    /// The fast path, for when nothing is watching and only the built in
+    /// opcodes are in use
+    fn resume_plain(&mut self) -> Result<CpuResult<W>, CpuError<W>> {
+        loop {
+            let instruction = self.decode_plain()?;
+            if let Some(result) = self.execute(instruction, None)? {
+                break Ok(result);
+            }
+        }
+    }
+
+    fn resume_stepped(&mut self) -> Result<CpuResult<W>, CpuError<W>> {
+        if let Some(profile) = &mut self.profile {
+            profile.begin_resume();
+        }
         loop {
-            match self.memory[self.pc] % 100 {
-                1 => self.i_add(),
-                2 => self.i_mul(),
-                3 => break CpuResult::Input,
-                4 => break CpuResult::Output(self.i_out()),
-                5 => self.i_jnz(),
-                6 => self.i_jz(),
-                7 => self.i_lt(),
-                8 => self.i_eq(),
-                9 => self.i_arbo(),
+            if let Some(result) = self.step()?.result {
+                break Ok(result);
+            }
+        }
+    }
+
+    pub fn resume_for(&mut self, budget: u64) -> Budget<W> {
+        unwrap(self.try_resume_for(budget))
+    }
+
+    /// Like `try_resume`, but gives up with `Budget::Yield` once `budget`
+    /// instructions have run.
+    ///
+    /// Running out of budget is reported through `Budget` rather than as a
+    /// `CpuResult::Yield` variant. `resume` and `run` can never yield, and a
+    /// separate type keeps every `match` on their results from needing an
+    /// arm that can't happen.
+    pub fn try_resume_for(&mut self, budget: u64) -> Result<Budget<W>, CpuError<W>> {
+        let stepped = self.needs_step();
+        if let Some(profile) = &mut self.profile {
+            profile.begin_resume();
+        }
+        for _ in 0..budget {
+            let result = if stepped {
+                self.step()?.result
+            } else {
+                let instruction = self.decode_plain()?;
+                self.execute(instruction, None)?
+            };
+            if let Some(result) = result {
+                return Ok(Budget::Done(result));
+            }
+        }
+        Ok(Budget::Yield)
+    }
+
+    /// Whether instructions have to go through `step`, because something
+    /// is watching them or there are custom opcodes to decode
+    fn needs_step(&self) -> bool {
+        self.is_instrumented() || !self.opcodes.is_empty()
+    }
+
+    /// Whether tracing, profiling, hooks or loop detection want to see every
+    /// instruction
+    pub(crate) fn is_instrumented(&self) -> bool {
+        self.tracer.is_some()
+            || self.profile.is_some()
+            || !self.hooks.is_empty()
+            || self.loops.is_some()
+    }
+
+    pub fn decode(&self, pc: usize) -> Result<Instruction<W>, CpuError<W>> {
+        match decode_with(|addr| self.memory.read(addr), pc) {
+            Err(err) if !self.opcodes.is_empty() => self.decode_custom(err),
+            result => result,
+        }
+    }
+
+    /// Decodes the instruction at `pc`, without looking for custom opcodes
+    #[inline(always)]
+    fn decode_plain(&self) -> Result<Instruction<W>, CpuError<W>> {
+        decode_with(|addr| self.memory.read(addr), self.pc)
+    }
+
+    /// Executes the instruction at `pc`. Input instructions are not executed,
+    /// they report `CpuResult::Input` and wait for a call to `input`.
+    pub fn step(&mut self) -> Result<Step<W>, CpuError<W>> {
+        let pc = self.pc;
+        let instruction = self.decode(pc)?;
+        let result = if self.hooks.is_empty() {
+            self.dispatch(instruction.clone(), None)?
+        } else {
+            self.hooked(instruction.clone(), None)?
+        };
+        if let Some(profile) = &mut self.profile {
+            // Input instructions only count once they're given their input
+            if result != Some(CpuResult::Input) {
+                profile.record(pc, &instruction, self.pc);
+            }
+            if result.is_some() {
+                profile.end_resume();
+            }
+        }
+        self.check_loop(&result)?;
+
+        Ok(Step {
+            pc,
+            instruction,
+            result,
+        })
+    }
+
+    pub fn input(&mut self, input: W) {
+        unwrap(self.try_input(input))
+    }
+
+    pub fn try_input(&mut self, input: W) -> Result<(), CpuError<W>> {
+        if self.needs_step() {
+            self.input_stepped(input)
+        } else {
+            self.input_plain(input)
+        }
+    }
 
-                // Halt
-                99 => break CpuResult::Halt,
-                op => panic!("Unknown opcode {}", op),
+    fn input_plain(&mut self, input: W) -> Result<(), CpuError<W>> {
+        match self.decode_plain()? {
+            instruction @ Instruction::In(_) => {
+                self.execute(instruction, Some(input))?;
+                Ok(())
             }
+            _ => Err(self.error(ErrorKind::UnexpectedInput)),
         }
     }
 
-    pub fn input(&mut self, input: i64) {
-        self.i_in(input);
+    fn input_stepped(&mut self, input: W) -> Result<(), CpuError<W>> {
+        let pc = self.pc;
+        let instruction = self.decode(pc)?;
+        if let Instruction::In(_) = instruction {
+            if self.hooks.is_empty() {
+                self.dispatch(instruction.clone(), Some(input))?;
+            } else {
+                self.hooked(instruction.clone(), Some(input))?;
+            }
+            if let Some(profile) = &mut self.profile {
+                profile.record(pc, &instruction, self.pc);
+            }
+            Ok(())
+        } else {
+            Err(self.error(ErrorKind::UnexpectedInput))
+        }
     }
 
-    pub fn compute(&mut self, input: i64) -> i64 {
-        let mut io = SingleIO::new(input);
-        self.run(&mut io);
-        io.output
+    /// Runs a decoded instruction, tracing it if there's a tracer
+    fn dispatch(
+        &mut self,
+        instruction: Instruction<W>,
+        input: Option<W>,
+    ) -> Result<Option<CpuResult<W>>, CpuError<W>> {
+        match self.tracer {
+            None => self.execute(instruction, input),
+            Some(_) => self.traced(instruction, input),
+        }
+    }
+
+    /// Runs a decoded instruction. `In` only executes when given an input.
+    #[inline(always)]
+    fn execute(
+        &mut self,
+        instruction: Instruction<W>,
+        input: Option<W>,
+    ) -> Result<Option<CpuResult<W>>, CpuError<W>> {
+        Ok(match instruction {
+            Instruction::Add(a, b, out) => {
+                self.i_add(a, b, out)?;
+                None
+            }
+            Instruction::Mul(a, b, out) => {
+                self.i_mul(a, b, out)?;
+                None
+            }
+            Instruction::In(out) => match input {
+                Some(input) => {
+                    self.i_in(out, input)?;
+                    None
+                }
+                None => Some(CpuResult::Input),
+            },
+            Instruction::Out(a) => Some(CpuResult::Output(self.i_out(a)?)),
+            Instruction::Jnz(cond, target) => {
+                self.i_jnz(cond, target)?;
+                None
+            }
+            Instruction::Jz(cond, target) => {
+                self.i_jz(cond, target)?;
+                None
+            }
+            Instruction::Lt(a, b, out) => {
+                self.i_lt(a, b, out)?;
+                None
+            }
+            Instruction::Eq(a, b, out) => {
+                self.i_eq(a, b, out)?;
+                None
+            }
+            Instruction::Arbo(a) => {
+                self.i_arbo(a)?;
+                None
+            }
+            Instruction::Halt => Some(CpuResult::Halt),
+            Instruction::Custom { opcode, arity } => {
+                self.i_custom(opcode, arity)?;
+                None
+            }
+        })
+    }
+
+    pub fn compute(&mut self, input: W) -> W {
+        unwrap(self.try_compute(input))
+    }
+
+    pub fn try_compute(&mut self, input: W) -> Result<W, CpuError<W>> {
+        let mut io = SingleIO {
+            input,
+            output: W::zero(),
+        };
+        // `SingleIO` never pauses or stops, so this only returns on a halt
+        let _ = self.try_run(&mut io)?;
+        Ok(io.output)
+    }
+
+    fn error(&self, kind: ErrorKind<W>) -> CpuError<W> {
+        CpuError {
+            pc: self.pc,
+            instruction: self.read(self.pc),
+            kind,
+        }
     }
 }
 
-pub enum CpuResult {
+/// Instructions `run_async` runs before letting other tasks go
+const ASYNC_BUDGET: u64 = 10_000;
+
+/// Pending once, so the executor polls everything else first
+async fn yield_now() {
+    let mut yielded = false;
+    future::poll_fn(|cx| {
+        if yielded {
+            return Poll::Ready(());
+        }
+        yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    })
+    .await
+}
+
+pub(super) fn unwrap<T, W: Word>(result: Result<T, CpuError<W>>) -> T {
+    match result {
+        Ok(value) => value,
+        Err(err) => panic!("{}", err),
+    }
+}
+
+/// Record of a single executed instruction
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Step<W = i64> {
+    pub pc: usize,
+    pub instruction: Instruction<W>,
+    pub result: Option<CpuResult<W>>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CpuResult<W = i64> {
     Halt,
     Input,
-    Output(i64),
+    Output(W),
+}
+
+/// How far `resume_for` got
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Budget<W = i64> {
+    Done(CpuResult<W>),
+    /// Ran out of instructions, resuming carries on from here
+    Yield,
 }
@@ -0,0 +1,62 @@
+use super::{unwrap, Cpu, CpuError, CpuResult, ErrorKind, Memory, Word};
+
+/// Iterator over a program's outputs, see `Cpu::outputs`
+pub struct Outputs<'a, I, W = i64, M = Vec<W>> {
+    cpu: &'a mut Cpu<W, M>,
+    inputs: I,
+    done: bool,
+}
+
+impl<W: Word, M: Memory<W>> Cpu<W, M> {
+    /// Runs the program as its outputs are iterated over, taking values from
+    /// `inputs` as it asks for them. The iterator ends when the program
+    /// halts, or after yielding the error it failed with.
+    pub fn outputs<I: IntoIterator<Item = W>>(
+        &mut self,
+        inputs: I,
+    ) -> Outputs<'_, I::IntoIter, W, M> {
+        Outputs {
+            cpu: self,
+            inputs: inputs.into_iter(),
+            done: false,
+        }
+    }
+
+    pub fn run_collect(&mut self, inputs: &[W]) -> Vec<W> {
+        unwrap(self.try_run_collect(inputs))
+    }
+
+    /// Runs until the program halts, returning everything it output.
+    /// Asking for more input than given is an `ErrorKind::OutOfInput`.
+    pub fn try_run_collect(&mut self, inputs: &[W]) -> Result<Vec<W>, CpuError<W>> {
+        self.outputs(inputs.iter().cloned()).collect()
+    }
+}
+
+impl<W: Word, M: Memory<W>, I: Iterator<Item = W>> Outputs<'_, I, W, M> {
+    fn advance(&mut self) -> Result<Option<W>, CpuError<W>> {
+        loop {
+            match self.cpu.try_resume()? {
+                CpuResult::Output(value) => return Ok(Some(value)),
+                CpuResult::Input => match self.inputs.next() {
+                    Some(input) => self.cpu.try_input(input)?,
+                    None => return Err(self.cpu.error(ErrorKind::OutOfInput)),
+                },
+                CpuResult::Halt => return Ok(None),
+            }
+        }
+    }
+}
+
+impl<W: Word, M: Memory<W>, I: Iterator<Item = W>> Iterator for Outputs<'_, I, W, M> {
+    type Item = Result<W, CpuError<W>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let next = self.advance().transpose();
+        self.done = !matches!(next, Some(Ok(_)));
+        next
+    }
+}
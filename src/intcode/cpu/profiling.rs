@@ -0,0 +1,17 @@
+use super::{Cpu, Memory, Word};
+use crate::intcode::profile::Profile;
+
+impl<W: Word, M: Memory<W>> Cpu<W, M> {
+    /// Starts counting executed instructions, discarding any previous profile
+    pub fn start_profile(&mut self) {
+        self.profile = Some(Box::default());
+    }
+
+    pub fn stop_profile(&mut self) -> Option<Profile> {
+        self.profile.take().map(|profile| *profile)
+    }
+
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_deref()
+    }
+}
@@ -0,0 +1,96 @@
+use super::{Arithmetic, Cpu, Instruction};
+
+use std::io::{self, Read, Write};
+
+const MAGIC: &[u8; 4] = b"ICSV";
+const VERSION: u8 = 2;
+
+const STATE_RUNNING: u8 = 0;
+const STATE_INPUT: u8 = 1;
+const STATE_HALTED: u8 = 2;
+
+// Save format, all integers little endian:
+//
+//   magic   "ICSV"
+//   version u8
+//   state   u8, whether the cpu was waiting for input or halted
+//   arith   u8, 0 wrapping, 1 checked, 2 saturating
+//   pc      u64
+//   rbo     i64
+//   length  u64
+//   memory  length * i64
+impl Cpu {
+    pub fn save(&self, out: &mut impl Write) -> io::Result<()> {
+        out.write_all(MAGIC)?;
+        let arithmetic = match self.arithmetic {
+            Arithmetic::Wrapping => 0,
+            Arithmetic::Checked => 1,
+            Arithmetic::Saturating => 2,
+        };
+        out.write_all(&[VERSION, self.pending_state(), arithmetic])?;
+        out.write_all(&(self.pc as u64).to_le_bytes())?;
+        out.write_all(&self.rbo.to_le_bytes())?;
+        out.write_all(&(self.memory.len() as u64).to_le_bytes())?;
+        for word in &self.memory {
+            out.write_all(&word.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub fn load(mut input: impl Read) -> io::Result<Cpu> {
+        let mut header = [0; 7];
+        input.read_exact(&mut header)?;
+        if &header[..4] != MAGIC {
+            return Err(invalid("not an Intcode save state"));
+        }
+        if header[4] != VERSION {
+            return Err(invalid("unsupported save state version"));
+        }
+
+        let arithmetic = match header[6] {
+            0 => Arithmetic::Wrapping,
+            1 => Arithmetic::Checked,
+            2 => Arithmetic::Saturating,
+            _ => return Err(invalid("unknown arithmetic")),
+        };
+        let pc = read_u64(&mut input)? as usize;
+        let rbo = read_u64(&mut input)? as i64;
+        let len = read_u64(&mut input)? as usize;
+
+        // Don't trust the length for the allocation, a corrupt file
+        // shouldn't be able to ask for terabytes up front
+        let mut memory = Vec::with_capacity(len.min(1 << 20));
+        for _ in 0..len {
+            memory.push(read_u64(&mut input)? as i64);
+        }
+
+        let cpu = Cpu {
+            pc,
+            rbo,
+            arithmetic,
+            ..Cpu::new(memory)
+        };
+        if cpu.pending_state() != header[5] {
+            return Err(invalid("save state doesn't match its memory"));
+        }
+        Ok(cpu)
+    }
+
+    fn pending_state(&self) -> u8 {
+        match self.decode(self.pc) {
+            Ok(Instruction::In(_)) => STATE_INPUT,
+            Ok(Instruction::Halt) => STATE_HALTED,
+            _ => STATE_RUNNING,
+        }
+    }
+}
+
+fn read_u64(input: &mut impl Read) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    input.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
@@ -0,0 +1,114 @@
+use super::custom::MemoryAccess;
+use super::{Cpu, CpuError, CpuResult, Instruction, Memory, Operand, Word};
+use crate::intcode::trace::{Access, IoEvent, TraceFormat, TraceRecord, Tracer};
+
+use smallvec::SmallVec;
+
+use std::io::{self, Write};
+
+impl<M: Memory> Cpu<i64, M> {
+    /// Records every instruction executed from now on to `out`
+    pub fn start_trace(&mut self, format: TraceFormat, out: impl Write + Send + 'static) {
+        self.tracer = Some(Tracer::new(format, out));
+    }
+
+    /// Stops tracing, flushing the trace and reporting any error writing it
+    pub fn stop_trace(&mut self) -> io::Result<()> {
+        match self.tracer.take() {
+            Some(tracer) => tracer.finish(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn tracer(&self) -> Option<&Tracer> {
+        self.tracer.as_ref()
+    }
+}
+
+/// Only `i64` cpus can start a trace, so every traced word fits
+fn word<W: Word>(word: &W) -> i64 {
+    word.to_i64().expect("traced a word that isn't an i64")
+}
+
+impl<W: Word, M: Memory<W>> Cpu<W, M> {
+    #[cold]
+    pub(super) fn traced(
+        &mut self,
+        instruction: Instruction<W>,
+        input: Option<W>,
+    ) -> Result<Option<CpuResult<W>>, CpuError<W>> {
+        if let (Instruction::In(_), None) = (&instruction, &input) {
+            return Ok(Some(CpuResult::Input));
+        }
+
+        let pc = self.pc;
+        // Addresses that don't resolve are left for `execute` to report, so
+        // the error is the same one an untraced cpu gives
+        let args = self
+            .used_operands(&instruction)?
+            .into_iter()
+            .map(|arg| self.access(arg))
+            .collect::<Result<SmallVec<_>, _>>();
+        let dest = instruction
+            .destination()
+            .and_then(|arg| self.arg_addr(arg).ok());
+
+        let opcode = instruction.opcode();
+        let arbo = matches!(instruction, Instruction::Arbo(_));
+        // Custom opcodes write through their `Extension`, maybe more than once
+        let (result, accesses) = match instruction {
+            Instruction::Custom { .. } => {
+                self.watched(|cpu| cpu.execute(instruction, input.clone()))
+            }
+            _ => (self.execute(instruction, input.clone()), Vec::new()),
+        };
+        let result = result?;
+        // Every operand used resolved, or the instruction would have failed
+        let args = args?;
+        let mut writes: SmallVec<_> = dest
+            .map(|addr| (addr, word(&self.read(addr))))
+            .into_iter()
+            .collect();
+        for access in accesses {
+            if let MemoryAccess::Write { addr, new, .. } = access {
+                writes.push((addr, word(&new)));
+            }
+        }
+
+        let io = match (&result, input) {
+            (Some(CpuResult::Output(value)), _) => Some(IoEvent::Output(word(value))),
+            (_, Some(value)) => Some(IoEvent::Input(word(&value))),
+            _ => None,
+        };
+        let record = TraceRecord {
+            n: 0,
+            pc,
+            opcode,
+            args,
+            writes,
+            rbo: if arbo { Some(word(&self.rbo)) } else { None },
+            io,
+        };
+        if let Some(tracer) = &mut self.tracer {
+            tracer.record(record);
+        }
+
+        Ok(result)
+    }
+
+    fn access(&self, arg: Operand<W>) -> Result<Access, CpuError<W>> {
+        match arg {
+            Operand::Immediate(value) => Ok(Access {
+                addr: None,
+                value: word(&value),
+            }),
+            _ => {
+                let addr = self.arg_addr(arg)?;
+                Ok(Access {
+                    addr: Some(addr),
+                    value: word(&self.read(addr)),
+                })
+            }
+        }
+    }
+}
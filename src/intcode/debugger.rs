@@ -0,0 +1,158 @@
+use super::{Cpu, CpuError, CpuResult, ErrorKind, Memory};
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::convert::TryFrom;
+
+pub struct Debugger {
+    pub cpu: Cpu,
+    pub breakpoints: BTreeSet<usize>,
+    /// Opcodes which stop execution before they run
+    pub op_breakpoints: BTreeSet<i64>,
+    /// Watched addresses along with the last value seen there
+    pub watchpoints: BTreeMap<usize, i64>,
+    pub inputs: VecDeque<i64>,
+    /// Values output since the last time this was drained
+    pub outputs: Vec<i64>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Stop {
+    /// The requested number of steps finished
+    Done,
+    Breakpoint(usize),
+    Opcode(i64),
+    Watchpoint {
+        addr: usize,
+        old: i64,
+        new: i64,
+    },
+    /// The program wants input and the queue is empty
+    NeedInput,
+    Halt,
+    Error(CpuError),
+}
+
+impl Debugger {
+    pub fn new(cpu: Cpu) -> Self {
+        Debugger {
+            cpu,
+            breakpoints: BTreeSet::new(),
+            op_breakpoints: BTreeSet::new(),
+            watchpoints: BTreeMap::new(),
+            inputs: VecDeque::new(),
+            outputs: Vec::new(),
+        }
+    }
+
+    pub fn watch(&mut self, addr: usize) {
+        let value = self.peek(addr);
+        self.watchpoints.insert(addr, value);
+    }
+
+    pub fn peek(&self, addr: usize) -> i64 {
+        self.cpu.memory.read(addr)
+    }
+
+    /// Writes `value` to `addr`, unless it's past what memory can hold
+    pub fn poke(&mut self, addr: usize, value: i64) -> Result<(), ErrorKind> {
+        if addr >= self.cpu.memory.limit() {
+            let addr = i64::try_from(addr).unwrap_or(i64::MAX);
+            return Err(ErrorKind::AddressOverflow(addr));
+        }
+        self.cpu.memory.write(addr, value);
+        if let Some(seen) = self.watchpoints.get_mut(&addr) {
+            *seen = value;
+        }
+        Ok(())
+    }
+
+    /// Executes up to `count` instructions, ignoring breakpoints
+    pub fn step(&mut self, count: usize) -> Stop {
+        for _ in 0..count {
+            if let Some(stop) = self.step_one() {
+                return stop;
+            }
+        }
+        Stop::Done
+    }
+
+    /// Runs until a breakpoint, watchpoint, halt or error. The instruction at
+    /// the current pc always runs, so continuing from a breakpoint works.
+    pub fn cont(&mut self) -> Stop {
+        self.run_until(|_| false)
+    }
+
+    /// Runs until execution reaches the instruction after the current one,
+    /// stepping over loops and calls
+    pub fn step_over(&mut self) -> Stop {
+        let after = match self.cpu.decode(self.cpu.pc) {
+            Ok(instr) => self.cpu.pc + instr.size(),
+            Err(err) => return Stop::Error(err),
+        };
+        self.run_until(|pc| pc == after)
+    }
+
+    fn run_until(&mut self, done: impl Fn(usize) -> bool) -> Stop {
+        if let Some(stop) = self.step_one() {
+            return stop;
+        }
+        loop {
+            let pc = self.cpu.pc;
+            if done(pc) {
+                return Stop::Done;
+            }
+            if self.breakpoints.contains(&pc) {
+                return Stop::Breakpoint(pc);
+            }
+            if !self.op_breakpoints.is_empty() {
+                let op = self.peek(pc) % 100;
+                if self.op_breakpoints.contains(&op) {
+                    return Stop::Opcode(op);
+                }
+            }
+            if let Some(stop) = self.step_one() {
+                return stop;
+            }
+        }
+    }
+
+    fn step_one(&mut self) -> Option<Stop> {
+        let result = match self.cpu.step() {
+            Ok(step) => step.result,
+            Err(err) => return Some(Stop::Error(err)),
+        };
+
+        match result {
+            None => (),
+            Some(CpuResult::Halt) => return Some(Stop::Halt),
+            Some(CpuResult::Output(value)) => self.outputs.push(value),
+            Some(CpuResult::Input) => match self.inputs.pop_front() {
+                None => return Some(Stop::NeedInput),
+                Some(value) => {
+                    if let Err(err) = self.cpu.try_input(value) {
+                        self.inputs.push_front(value);
+                        return Some(Stop::Error(err));
+                    }
+                }
+            },
+        }
+
+        self.check_watchpoints()
+    }
+
+    fn check_watchpoints(&mut self) -> Option<Stop> {
+        let memory = &self.cpu.memory;
+        for (&addr, seen) in self.watchpoints.iter_mut() {
+            let value = memory.read(addr);
+            if value != *seen {
+                let old = std::mem::replace(seen, value);
+                return Some(Stop::Watchpoint {
+                    addr,
+                    old,
+                    new: value,
+                });
+            }
+        }
+        None
+    }
+}
@@ -0,0 +1,178 @@
+use super::cpu::{decode, Instruction, Operand};
+
+use std::fmt;
+
+const DATA_WORDS_PER_LINE: usize = 8;
+
+pub struct Listing {
+    pub lines: Vec<Line>,
+}
+
+pub struct Line {
+    pub addr: usize,
+    pub words: Vec<i64>,
+    pub kind: LineKind,
+    /// Instructions starting inside this one, reached by jumps into its
+    /// operands. They're shown as comments after it.
+    pub overlaps: Vec<(usize, Instruction)>,
+}
+
+pub enum LineKind {
+    Code(Instruction),
+    /// Words which no discovered control flow reaches. These are probably
+    /// data, but may be code only reached through computed jumps.
+    Data,
+}
+
+/// Disassembles a whole program, treating everything reachable from address 0
+/// as code and the rest as data.
+pub fn disassemble(program: &[i64]) -> Listing {
+    let code = find_code(program);
+    let mut lines = Vec::new();
+
+    let mut addr = 0;
+    while addr < program.len() {
+        if code[addr] {
+            let instr = decode(program, addr).expect("code was decoded during discovery");
+            let end = (addr + instr.size()).min(program.len());
+            let overlaps = (addr + 1..end)
+                .filter(|&a| code[a])
+                .filter_map(|a| decode(program, a).ok().map(|instr| (a, instr)))
+                .collect();
+            lines.push(Line {
+                addr,
+                words: program[addr..end].to_vec(),
+                kind: LineKind::Code(instr),
+                overlaps,
+            });
+            addr = end;
+        } else {
+            let mut end = addr + 1;
+            while end < program.len() && !code[end] && end - addr < DATA_WORDS_PER_LINE {
+                end += 1;
+            }
+            lines.push(Line {
+                addr,
+                words: program[addr..end].to_vec(),
+                kind: LineKind::Data,
+                overlaps: Vec::new(),
+            });
+            addr = end;
+        }
+    }
+
+    Listing { lines }
+}
+
+/// Marks the start of every instruction reachable from address 0. Jumps are
+/// only followed when their target is an immediate, so return addresses
+/// pushed onto the stack are also treated as entry points.
+fn find_code(program: &[i64]) -> Vec<bool> {
+    let mut code = vec![false; program.len()];
+    let mut pending = vec![0];
+
+    while let Some(addr) = pending.pop() {
+        if addr >= program.len() || code[addr] {
+            continue;
+        }
+        let instr = match decode(program, addr) {
+            Ok(instr) => instr,
+            Err(_) => continue,
+        };
+        code[addr] = true;
+
+        let next = addr + instr.size();
+        let target = |op| match op {
+            Operand::Immediate(target) if target >= 0 => Some(target as usize),
+            _ => None,
+        };
+
+        match instr {
+            Instruction::Halt => (),
+            Instruction::Jnz(Operand::Immediate(cond), dest) if cond != 0 => {
+                pending.extend(target(dest));
+            }
+            Instruction::Jz(Operand::Immediate(0), dest) => {
+                pending.extend(target(dest));
+            }
+            Instruction::Jnz(_, dest) | Instruction::Jz(_, dest) => {
+                pending.extend(target(dest));
+                pending.push(next);
+            }
+            _ => {
+                if let Some(ret) = return_address(&instr) {
+                    pending.extend(target(Operand::Immediate(ret)));
+                }
+                pending.push(next);
+            }
+        }
+    }
+
+    code
+}
+
+/// Recognises `add #ret, #0, @n` and `mul #ret, #1, @n`, the usual ways of
+/// pushing a return address before a call
+fn return_address(instr: &Instruction) -> Option<i64> {
+    use Operand::{Immediate, Relative};
+
+    match *instr {
+        Instruction::Add(Immediate(a), Immediate(b), Relative(_)) if a == 0 || b == 0 => {
+            Some(a + b)
+        }
+        Instruction::Mul(Immediate(a), Immediate(b), Relative(_)) if a == 1 || b == 1 => {
+            Some(a * b)
+        }
+        _ => None,
+    }
+}
+
+impl fmt::Display for Listing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for line in &self.lines {
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let join = |sep| {
+            let words: Vec<_> = self.words.iter().map(|w| w.to_string()).collect();
+            words.join(sep)
+        };
+
+        // The directive already shows data words, so they aren't repeated
+        let (words, text) = match &self.kind {
+            LineKind::Code(instr) => (join(" "), instr.to_string()),
+            LineKind::Data => (String::new(), format!(".data {}", join(", "))),
+        };
+
+        write!(f, "{:>6}: {:<24} {}", self.addr, words, text)?;
+        if let LineKind::Data = self.kind {
+            if let Some(ascii) = ascii(&self.words) {
+                write!(f, "  ; {:?}", ascii)?;
+            }
+        }
+        for (addr, instr) in &self.overlaps {
+            write!(f, "\n{:>6}: {:<24} ; overlapping: {}", addr, "", instr)?;
+        }
+        Ok(())
+    }
+}
+
+/// Renders data words as text if there's more than one and they're all
+/// printable ASCII or newlines
+fn ascii(words: &[i64]) -> Option<String> {
+    if words.len() < 2 {
+        return None;
+    }
+    words
+        .iter()
+        .map(|&w| match w {
+            10 | 32..=126 => Some(w as u8 as char),
+            _ => None,
+        })
+        .collect()
+}
@@ -0,0 +1,93 @@
+//! A single threaded executor, enough to run lots of cpus talking to each
+//! other through `asyncio::channel`s.
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+
+type Task = Pin<Box<dyn Future<Output = ()>>>;
+
+#[derive(Default)]
+pub struct Executor {
+    tasks: Vec<Option<Task>>,
+    ready: Arc<Mutex<VecDeque<usize>>>,
+}
+
+struct TaskWaker {
+    task: usize,
+    ready: Arc<Mutex<VecDeque<usize>>>,
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.ready.lock().unwrap().push_back(self.task);
+    }
+}
+
+impl Executor {
+    pub fn new() -> Self {
+        Executor::default()
+    }
+
+    pub fn spawn(&mut self, task: impl Future<Output = ()> + 'static) {
+        self.ready.lock().unwrap().push_back(self.tasks.len());
+        self.tasks.push(Some(Box::pin(task)));
+    }
+
+    /// Runs until no task can make progress. Tasks still waiting are kept,
+    /// running again carries on with them.
+    pub fn run(&mut self) {
+        loop {
+            let next = self.ready.lock().unwrap().pop_front();
+            let id = match next {
+                Some(id) => id,
+                None => break,
+            };
+            // Already finished, woken more than once
+            let task = match &mut self.tasks[id] {
+                Some(task) => task,
+                None => continue,
+            };
+            let waker = Waker::from(Arc::new(TaskWaker {
+                task: id,
+                ready: self.ready.clone(),
+            }));
+            if task
+                .as_mut()
+                .poll(&mut Context::from_waker(&waker))
+                .is_ready()
+            {
+                self.tasks[id] = None;
+            }
+        }
+    }
+
+    /// Number of tasks that haven't finished
+    pub fn pending(&self) -> usize {
+        self.tasks.iter().filter(|task| task.is_some()).count()
+    }
+}
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Runs a future to completion on the current thread
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = Box::pin(future);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => break output,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
@@ -0,0 +1,59 @@
+//! Observers attached to a `Cpu` with `add_hook`. Every method does nothing
+//! by default, so a hook only implements the events it cares about.
+//!
+//! Hooks are boxed by the `Cpu`, so to look at what one collected afterwards
+//! share it through an `Arc<Mutex<_>>`, which is a hook itself. Hooks have
+//! to be `Send` so the `Cpu` is too.
+
+use std::sync::{Arc, Mutex};
+
+#[allow(unused_variables)]
+pub trait Hook<W = i64> {
+    /// A memory operand was read. Instruction fetches don't count.
+    fn read(&mut self, addr: usize, value: W) {}
+
+    fn write(&mut self, addr: usize, old: W, new: W) {}
+
+    /// A conditional jump at `pc` was taken
+    fn jump(&mut self, pc: usize, target: usize) {}
+
+    /// The relative base moved from `old` to `new`
+    fn rbo(&mut self, old: W, new: W) {}
+
+    fn input(&mut self, value: W) {}
+
+    fn output(&mut self, value: W) {}
+
+    /// The program halted at `pc`
+    fn halt(&mut self, pc: usize) {}
+}
+
+impl<W, H: Hook<W>> Hook<W> for Arc<Mutex<H>> {
+    fn read(&mut self, addr: usize, value: W) {
+        self.lock().unwrap().read(addr, value)
+    }
+
+    fn write(&mut self, addr: usize, old: W, new: W) {
+        self.lock().unwrap().write(addr, old, new)
+    }
+
+    fn jump(&mut self, pc: usize, target: usize) {
+        self.lock().unwrap().jump(pc, target)
+    }
+
+    fn rbo(&mut self, old: W, new: W) {
+        self.lock().unwrap().rbo(old, new)
+    }
+
+    fn input(&mut self, value: W) {
+        self.lock().unwrap().input(value)
+    }
+
+    fn output(&mut self, value: W) {
+        self.lock().unwrap().output(value)
+    }
+
+    fn halt(&mut self, pc: usize) {
+        self.lock().unwrap().halt(pc)
+    }
+}
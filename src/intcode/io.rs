@@ -1,52 +1,115 @@
+use super::{CpuResult, Word};
+
+use std::collections::VecDeque;
 use std::io::Write;
 use std::sync::mpsc::{Receiver, Sender};
 
-pub trait IO {
-    fn input(&mut self) -> i64;
-    fn output(&mut self, value: i64);
+pub trait IO<W = i64> {
+    fn input(&mut self) -> Input<W>;
+    fn output(&mut self, value: W) -> Flow;
+}
+
+/// What the cpu gets when it asks for input
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Input<W = i64> {
+    Value(W),
+    /// No input yet. Running again asks for it again.
+    Pause,
+    /// There's no more input
+    Stop,
+}
+
+/// Whether the cpu carries on after an output
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Flow {
+    Continue,
+    /// Stop for now, running again carries on after the output
+    Pause,
+    Stop,
 }
 
-impl<T: IO> IO for &mut T {
-    fn input(&mut self) -> i64 {
+/// Why `Cpu::run` returned
+#[must_use = "the program may have stopped before halting"]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Exit {
+    /// The program halted
+    Halt,
+    /// The `IO` paused
+    Pause,
+    /// The `IO` stopped
+    Stop,
+}
+
+/// The `try_run` loop shared by every engine, driving `cpu` with its own
+/// `resume` and `input` until it halts or `io` pauses or stops it
+pub(crate) fn run_with<T, W, E>(
+    cpu: &mut T,
+    mut io: impl IO<W>,
+    resume: impl Fn(&mut T) -> Result<CpuResult<W>, E>,
+    input: impl Fn(&mut T, W) -> Result<(), E>,
+) -> Result<Exit, E> {
+    loop {
+        match resume(cpu)? {
+            CpuResult::Halt => break Ok(Exit::Halt),
+            CpuResult::Input => match io.input() {
+                Input::Value(value) => input(cpu, value)?,
+                Input::Pause => break Ok(Exit::Pause),
+                Input::Stop => break Ok(Exit::Stop),
+            },
+            CpuResult::Output(out) => match io.output(out) {
+                Flow::Continue => (),
+                Flow::Pause => break Ok(Exit::Pause),
+                Flow::Stop => break Ok(Exit::Stop),
+            },
+        }
+    }
+}
+
+impl<W, T: IO<W>> IO<W> for &mut T {
+    fn input(&mut self) -> Input<W> {
         T::input(self)
     }
-    fn output(&mut self, value: i64) {
+    fn output(&mut self, value: W) -> Flow {
         T::output(self, value)
     }
 }
 
-impl IO for () {
-    fn input(&mut self) -> i64 {
-        unimplemented!()
+/// For programs that don't take input. Outputs are thrown away.
+impl<W> IO<W> for () {
+    fn input(&mut self) -> Input<W> {
+        Input::Stop
     }
-    fn output(&mut self, _value: i64) {
-        unimplemented!()
+    fn output(&mut self, _value: W) -> Flow {
+        Flow::Continue
     }
 }
 
 pub struct StdIO;
 
-impl IO for StdIO {
-    fn input(&mut self) -> i64 {
+impl<W: Word> IO<W> for StdIO {
+    fn input(&mut self) -> Input<W> {
         loop {
             print!("Please enter a number: ");
             std::io::stdout().flush().expect("um");
             let mut line = String::new();
-            std::io::stdin().read_line(&mut line).expect("um");
+            if std::io::stdin().read_line(&mut line).expect("um") == 0 {
+                return Input::Stop;
+            }
             match line.trim().parse() {
-                Ok(i) => return i,
+                Ok(i) => return Input::Value(i),
                 Err(_) => println!("❌"),
             }
         }
     }
-    fn output(&mut self, value: i64) {
+    fn output(&mut self, value: W) -> Flow {
         println!("Output: {}", value);
+        Flow::Continue
     }
 }
 
-pub struct SingleIO {
-    pub input: i64,
-    pub output: i64,
+pub struct SingleIO<W = i64> {
+    pub input: W,
+    pub output: W,
 }
 
 impl SingleIO {
@@ -55,37 +118,106 @@ impl SingleIO {
     }
 }
 
-impl IO for SingleIO {
-    fn input(&mut self) -> i64 {
-        self.input
+impl<W: Clone> IO<W> for SingleIO<W> {
+    fn input(&mut self) -> Input<W> {
+        Input::Value(self.input.clone())
     }
-    fn output(&mut self, value: i64) {
+    fn output(&mut self, value: W) -> Flow {
         self.output = value;
+        Flow::Continue
     }
 }
 
-pub struct ChannelIO {
-    input: Receiver<i64>,
-    output: Sender<i64>,
-    pub last_output: i64,
+pub struct ChannelIO<W = i64> {
+    input: Receiver<W>,
+    output: Sender<W>,
+    pub last_output: W,
 }
 
-impl ChannelIO {
-    pub fn new(input: Receiver<i64>, output: Sender<i64>) -> Self {
+impl<W: Word> ChannelIO<W> {
+    pub fn new(input: Receiver<W>, output: Sender<W>) -> Self {
         ChannelIO {
             input,
             output,
-            last_output: 0,
+            last_output: W::zero(),
+        }
+    }
+}
+
+/// Stops once the other end of either channel is gone
+impl<W: Word> IO<W> for ChannelIO<W> {
+    fn input(&mut self) -> Input<W> {
+        match self.input.recv() {
+            Ok(value) => Input::Value(value),
+            Err(_) => Input::Stop,
+        }
+    }
+    fn output(&mut self, value: W) -> Flow {
+        self.last_output = value.clone();
+        match self.output.send(value) {
+            Ok(()) => Flow::Continue,
+            Err(_) => Flow::Stop,
         }
     }
 }
 
-impl IO for ChannelIO {
-    fn input(&mut self) -> i64 {
-        self.input.recv().unwrap_or(0)
+/// For programs that speak ASCII. Output is collected into lines, with
+/// anything outside ASCII kept as a number, and input is given a line at a
+/// time. Running out of input pauses the cpu until another line is pushed.
+#[derive(Default)]
+pub struct AsciiIO {
+    input: VecDeque<i64>,
+    line: String,
+    pub lines: Vec<String>,
+    pub values: Vec<i64>,
+}
+
+impl AsciiIO {
+    pub fn new() -> Self {
+        AsciiIO::default()
+    }
+
+    /// Queues `line` to be read a character at a time, then a newline
+    pub fn push_line(&mut self, line: &str) {
+        self.input.extend(line.bytes().map(i64::from));
+        self.input.push_back(i64::from(b'\n'));
+    }
+
+    pub fn has_input(&self) -> bool {
+        !self.input.is_empty()
+    }
+
+    /// Output since the last newline, usually a prompt
+    pub fn partial(&self) -> &str {
+        &self.line
     }
-    fn output(&mut self, value: i64) {
-        self.last_output = value;
-        self.output.send(value).ok();
+
+    /// The lines output so far, including any partial one
+    pub fn take_text(&mut self) -> String {
+        let mut text = String::new();
+        for line in self.lines.drain(..) {
+            text.push_str(&line);
+            text.push('\n');
+        }
+        text.push_str(&self.line);
+        self.line.clear();
+        text
+    }
+}
+
+impl IO for AsciiIO {
+    fn input(&mut self) -> Input {
+        match self.input.pop_front() {
+            Some(value) => Input::Value(value),
+            None => Input::Pause,
+        }
+    }
+    fn output(&mut self, value: i64) -> Flow {
+        match value {
+            10 => self.lines.push(std::mem::take(&mut self.line)),
+            0..=127 => self.line.push(value as u8 as char),
+            _ => self.values.push(value),
+        }
+        Flow::Continue
     }
 }
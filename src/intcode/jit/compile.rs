@@ -0,0 +1,285 @@
+//! Translates basic blocks into x86-64.
+//!
+//! While running native code the registers hold:
+//!
+//! - `rbx`: memory, `r12`: its length in words
+//! - `r13`: the relative base
+//! - `r14`: the `Context`
+//! - `r15`: a byte per word, set for opcodes that have been compiled
+//! - `rbp`: the native code for each address, or null
+//!
+//! Operands are always read from memory, so programs are free to patch them.
+//! Only the opcode (and with it the addressing modes and instruction size) is
+//! compiled in, and writing one leaves with `Exit::Stale`. Anything else the
+//! native code can't do (reading or writing outside memory, jumping outside
+//! it, a negative address) leaves with `Exit::Step` before the instruction
+//! has had any effect, for the interpreter to run it.
+
+use super::x86::*;
+use super::Exit;
+use crate::intcode::cpu::{decode, Instruction, Operand};
+
+use std::mem::size_of;
+
+/// Longest block, in instructions
+const MAX_INSTRUCTIONS: usize = 32;
+/// More than a block of `MAX_INSTRUCTIONS` could ever need
+pub const MAX_BLOCK_BYTES: usize = 16 * 1024;
+/// Addresses must fit in the displacement of a memory operand
+const MAX_ADDRESS: usize = (i32::MAX as usize) / size_of::<i64>() - 4;
+
+/// Offsets into `Context`
+const MEMORY: i32 = 0;
+const LEN: i32 = 8;
+const RBO: i32 = 16;
+const CODE: i32 = 24;
+const TABLE: i32 = 32;
+const PC: i32 = 40;
+const VALUE: i32 = 48;
+
+/// Shared code at the start of the pages
+pub struct Prelude {
+    pub code: Vec<u8>,
+    epilogue: usize,
+    /// Leaves with `Exit::Miss` for the address in `rax`
+    miss: usize,
+}
+
+/// `extern "C" fn(*mut Context, *const u8) -> Exit` which jumps into a block
+pub fn prelude() -> Prelude {
+    let mut asm = Assembler::new(0);
+    for &reg in &[RBX, RBP, R12, R13, R14, R15] {
+        asm.push(reg);
+    }
+    asm.mov(R14, RDI);
+    asm.load(RBX, Mem::at(R14, MEMORY));
+    asm.load(R12, Mem::at(R14, LEN));
+    asm.load(R13, Mem::at(R14, RBO));
+    asm.load(R15, Mem::at(R14, CODE));
+    asm.load(RBP, Mem::at(R14, TABLE));
+    asm.jmp_reg(RSI);
+
+    let epilogue = asm.here();
+    asm.store(Mem::at(R14, RBO), R13);
+    for &reg in &[R15, R14, R13, R12, RBP, RBX] {
+        asm.pop(reg);
+    }
+    asm.ret();
+
+    let miss = asm.here();
+    asm.store(Mem::at(R14, PC), RAX);
+    asm.mov_eax(Exit::Miss as u32);
+    asm.jmp(epilogue);
+
+    Prelude {
+        code: asm.code,
+        epilogue,
+        miss,
+    }
+}
+
+struct Block<'a> {
+    asm: Assembler,
+    prelude: &'a Prelude,
+    memory: &'a [i64],
+    /// Forward jumps to exits, emitted after the block
+    exits: Vec<(usize, Exit, usize)>,
+}
+
+/// Compiles the block starting at `pc` to go at `origin` in the pages, marking
+/// its opcodes in `code`. Returns `None` if there isn't a valid instruction
+/// at `pc`.
+pub fn block(
+    prelude: &Prelude,
+    memory: &[i64],
+    code: &mut [u8],
+    pc: usize,
+    origin: usize,
+) -> Option<Vec<u8>> {
+    let mut block = Block {
+        asm: Assembler::new(origin),
+        prelude,
+        memory,
+        exits: Vec::new(),
+    };
+
+    let mut addr = pc;
+    for count in 0.. {
+        let instr = match decode(memory, addr) {
+            // Operands are read straight from memory, so they have to be in it
+            Ok(instr) if addr + instr.size() <= memory.len() && addr < MAX_ADDRESS => instr,
+            _ if count == 0 => return None,
+            _ => {
+                block.goto(addr);
+                break;
+            }
+        };
+        code[addr] = 1;
+
+        let next = addr + instr.size();
+        if !block.instruction(addr, instr) {
+            break;
+        }
+        if count + 1 == MAX_INSTRUCTIONS {
+            block.goto(next);
+            break;
+        }
+        addr = next;
+    }
+
+    block.finish();
+    Some(block.asm.code)
+}
+
+impl Block<'_> {
+    /// Emits one instruction, returning false if it ends the block
+    fn instruction(&mut self, pc: usize, instr: Instruction) -> bool {
+        let next = pc + instr.size();
+        match instr {
+            Instruction::Add(a, b, out)
+            | Instruction::Mul(a, b, out)
+            | Instruction::Lt(a, b, out)
+            | Instruction::Eq(a, b, out) => {
+                self.load(RAX, pc, 1, a);
+                self.load(RCX, pc, 2, b);
+                match instr {
+                    Instruction::Add(..) => self.asm.add(RAX, RCX),
+                    Instruction::Mul(..) => self.asm.imul(RAX, RCX),
+                    Instruction::Lt(..) => {
+                        self.asm.cmp(RAX, RCX);
+                        self.asm.set_rax(CC_L);
+                    }
+                    _ => {
+                        self.asm.cmp(RAX, RCX);
+                        self.asm.set_rax(CC_E);
+                    }
+                }
+                self.store(pc, 3, out, next);
+                true
+            }
+            Instruction::Out(a) => {
+                self.load(RAX, pc, 1, a);
+                self.asm.store(Mem::at(R14, VALUE), RAX);
+                self.exit(Exit::Output, next);
+                false
+            }
+            Instruction::Jnz(cond, target) | Instruction::Jz(cond, target) => {
+                let jnz = matches!(instr, Instruction::Jnz(..));
+                self.load(RAX, pc, 1, cond);
+                self.asm.test(RAX, RAX);
+                let skip = self.asm.jcc_forward(if jnz { CC_E } else { CC_NE });
+
+                self.load(RAX, pc, 2, target);
+                self.exit_if(CC_AE, RAX, R12, Exit::Step, pc);
+                self.asm.load(RCX, Mem::words(RBP, RAX));
+                self.asm.test(RCX, RCX);
+                self.asm.jcc(CC_E, self.prelude.miss);
+                self.asm.jmp_reg(RCX);
+
+                let here = self.asm.here();
+                self.asm.patch(skip, here);
+
+                // A jump that's always taken is usually followed by data
+                match cond {
+                    Operand::Immediate(cond) => (cond != 0) != jnz,
+                    _ => true,
+                }
+            }
+            Instruction::Arbo(a) => {
+                self.load(RAX, pc, 1, a);
+                self.asm.add(R13, RAX);
+                true
+            }
+            Instruction::In(_) => {
+                self.exit(Exit::Input, pc);
+                false
+            }
+            Instruction::Halt => {
+                self.exit(Exit::Halt, pc);
+                false
+            }
+            Instruction::Custom { .. } => unreachable!("custom opcodes are never decoded here"),
+        }
+    }
+
+    /// Loads operand `arg` of the instruction at `pc` into `reg`
+    fn load(&mut self, reg: u8, pc: usize, arg: usize, operand: Operand) {
+        let word = Mem::at(RBX, ((pc + arg) * size_of::<i64>()) as i32);
+        self.asm.load(reg, word);
+        if let Operand::Immediate(_) = operand {
+            return;
+        }
+        if let Operand::Relative(_) = operand {
+            self.asm.add(reg, R13);
+            self.exit_on(CC_O, Exit::Step, pc);
+        }
+        // Unsigned, so negative addresses fail too
+        self.exit_if(CC_AE, reg, R12, Exit::Step, pc);
+        self.asm.load(reg, Mem::words(RBX, reg));
+    }
+
+    /// Stores `rax` to operand `arg` of the instruction at `pc`
+    fn store(&mut self, pc: usize, arg: usize, operand: Operand, next: usize) {
+        let word = Mem::at(RBX, ((pc + arg) * size_of::<i64>()) as i32);
+        self.asm.load(RCX, word);
+        if let Operand::Relative(_) = operand {
+            self.asm.add(RCX, R13);
+            self.exit_on(CC_O, Exit::Step, pc);
+        }
+        self.exit_if(CC_AE, RCX, R12, Exit::Step, pc);
+        self.asm.store(Mem::words(RBX, RCX), RAX);
+        self.asm.cmp_byte_zero(Mem::bytes(R15, RCX));
+        let stale = self.asm.jcc_forward(CC_NE);
+        self.exits.push((stale, Exit::Stale, next));
+    }
+
+    /// Continues at a known address
+    fn goto(&mut self, pc: usize) {
+        if pc >= self.memory.len() || pc >= MAX_ADDRESS {
+            self.exit(Exit::Miss, pc);
+            return;
+        }
+        let entry = Mem::at(RBP, (pc * size_of::<*const u8>()) as i32);
+        self.asm.load(RCX, entry);
+        self.asm.test(RCX, RCX);
+        let miss = self.asm.jcc_forward(CC_E);
+        self.exits.push((miss, Exit::Miss, pc));
+        self.asm.jmp_reg(RCX);
+    }
+
+    fn exit_if(&mut self, cc: u8, a: u8, b: u8, exit: Exit, pc: usize) {
+        self.asm.cmp(a, b);
+        self.exit_on(cc, exit, pc);
+    }
+
+    /// Exits if the flags already set meet `cc`
+    fn exit_on(&mut self, cc: u8, exit: Exit, pc: usize) {
+        let at = self.asm.jcc_forward(cc);
+        self.exits.push((at, exit, pc));
+    }
+
+    fn exit(&mut self, exit: Exit, pc: usize) {
+        self.asm.store_imm(Mem::at(R14, PC), pc as i32);
+        self.asm.mov_eax(exit as u32);
+        self.asm.jmp(self.prelude.epilogue);
+    }
+
+    fn finish(&mut self) {
+        let mut exits = std::mem::take(&mut self.exits);
+        exits.sort_by_key(|&(_, exit, pc)| (exit as u32, pc));
+
+        let mut last = None;
+        for (at, exit, pc) in exits {
+            let target = match last {
+                Some((target, same)) if same == (exit as u32, pc) => target,
+                _ => {
+                    let target = self.asm.here();
+                    self.exit(exit, pc);
+                    last = Some((target, (exit as u32, pc)));
+                    target
+                }
+            };
+            self.asm.patch(at, target);
+        }
+    }
+}
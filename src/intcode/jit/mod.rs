@@ -0,0 +1,265 @@
+//! An x86-64 JIT for Linux, enabled with the `jit` feature.
+//!
+//! Basic blocks are compiled the first time execution reaches them and jump
+//! straight into each other. Whenever the native code can't carry on it
+//! returns, and the instruction in question is run by an ordinary `Cpu`
+//! before going back to native code. Writing over a compiled opcode, or
+//! running a custom opcode, throws away everything compiled so far.
+//!
+//! Native arithmetic wraps on overflow, so a cpu using any other
+//! `Arithmetic` runs entirely in the interpreter. So does a cpu with
+//! tracing, profiling, hooks or loop detection, which only see instructions
+//! that go through `Cpu::step`.
+
+use super::cpu::unwrap;
+use super::io::run_with;
+// The native code has an `Exit` of its own
+use super::io::Exit as RunExit;
+use super::{Arithmetic, Cpu, CpuError, CpuResult, Instruction, Operand, SingleIO, IO};
+
+use self::compile::{Prelude, MAX_BLOCK_BYTES};
+use self::pages::Pages;
+
+#[cfg(not(all(target_arch = "x86_64", target_os = "linux")))]
+compile_error!("the `jit` feature only supports x86-64 Linux");
+
+mod compile;
+mod pages;
+mod x86;
+
+const PAGES: usize = 1 << 20;
+
+/// Why native code returned
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u32)]
+enum Exit {
+    /// The instruction at `pc` needs the interpreter
+    Step,
+    Input,
+    Output,
+    Halt,
+    /// Continuing at `pc` after writing over compiled code
+    Stale,
+    /// There's no compiled block at `pc` yet
+    Miss,
+}
+
+/// Shared with native code, see `compile` for the layout
+#[repr(C)]
+struct Context {
+    memory: *mut i64,
+    len: usize,
+    rbo: i64,
+    code: *const u8,
+    table: *const *const u8,
+    pc: usize,
+    value: i64,
+}
+
+type Entry = unsafe extern "C" fn(*mut Context, *const u8) -> Exit;
+
+pub struct JitCpu {
+    cpu: Cpu,
+    pages: Pages,
+    prelude: Prelude,
+    /// Where the next block goes in the pages
+    end: usize,
+    /// Native code for the block starting at each address
+    table: Vec<*const u8>,
+    /// Set for the opcode of each compiled instruction
+    code: Vec<u8>,
+}
+
+/// Everything carries over, and `into_cpu` gives it all back. A cpu with
+/// tracing, profiling, hooks or loop detection runs in the interpreter so
+/// they see every instruction.
+impl From<Cpu> for JitCpu {
+    fn from(cpu: Cpu) -> Self {
+        let prelude = compile::prelude();
+        let mut pages = Pages::new(PAGES).expect("Couldn't map pages for the JIT");
+        pages.write()[..prelude.code.len()].copy_from_slice(&prelude.code);
+        JitCpu {
+            cpu,
+            pages,
+            end: prelude.code.len(),
+            prelude,
+            table: Vec::new(),
+            code: Vec::new(),
+        }
+    }
+}
+
+impl JitCpu {
+    pub fn new(memory: Vec<i64>) -> Self {
+        Cpu::new(memory).into()
+    }
+
+    pub fn into_cpu(self) -> Cpu {
+        self.cpu
+    }
+
+    pub fn pc(&self) -> usize {
+        self.cpu.pc
+    }
+
+    pub fn rbo(&self) -> i64 {
+        self.cpu.rbo
+    }
+
+    pub fn memory(&self) -> &[i64] {
+        &self.cpu.memory
+    }
+
+    /// Mutable access to memory. This throws away all compiled code.
+    pub fn memory_mut(&mut self) -> &mut Vec<i64> {
+        self.invalidate();
+        &mut self.cpu.memory
+    }
+
+    pub fn run(&mut self, io: impl IO) -> RunExit {
+        unwrap(self.try_run(io))
+    }
+
+    pub fn try_run(&mut self, io: impl IO) -> Result<RunExit, CpuError> {
+        run_with(self, io, Self::try_resume, Self::try_input)
+    }
+
+    pub fn compute(&mut self, input: i64) -> i64 {
+        unwrap(self.try_compute(input))
+    }
+
+    pub fn try_compute(&mut self, input: i64) -> Result<i64, CpuError> {
+        let mut io = SingleIO::new(input);
+        let _ = self.try_run(&mut io)?;
+        Ok(io.output)
+    }
+
+    pub fn resume(&mut self) -> CpuResult {
+        unwrap(self.try_resume())
+    }
+
+    pub fn try_resume(&mut self) -> Result<CpuResult, CpuError> {
+        loop {
+            // The interpreter may have grown memory
+            let len = self.cpu.memory.len();
+            self.table.resize(len, std::ptr::null());
+            self.code.resize(len, 0);
+
+            let pc = self.cpu.pc;
+            let interpreted =
+                self.cpu.arithmetic != Arithmetic::Wrapping || self.cpu.is_instrumented();
+            let block = match self.table.get(pc) {
+                _ if interpreted => std::ptr::null(),
+                Some(&block) if !block.is_null() => block,
+                _ => self.compile(pc),
+            };
+            if block.is_null() {
+                match self.interpret()? {
+                    Some(result) => return Ok(result),
+                    None => continue,
+                }
+            }
+
+            let mut context = Context {
+                memory: self.cpu.memory.as_mut_ptr(),
+                len,
+                rbo: self.cpu.rbo,
+                code: self.code.as_ptr(),
+                table: self.table.as_ptr(),
+                pc,
+                value: 0,
+            };
+            self.pages.executable();
+            // Safety: the prelude is at the start of the pages, and the code
+            // only touches memory within the bounds in the context
+            let exit = unsafe {
+                let entry: Entry = std::mem::transmute(self.pages.base());
+                entry(&mut context, block)
+            };
+            self.cpu.pc = context.pc;
+            self.cpu.rbo = context.rbo;
+
+            match exit {
+                Exit::Input => return Ok(CpuResult::Input),
+                Exit::Output => return Ok(CpuResult::Output(context.value)),
+                Exit::Halt => return Ok(CpuResult::Halt),
+                Exit::Stale => self.invalidate(),
+                Exit::Miss => (),
+                Exit::Step => {
+                    if let Some(result) = self.interpret()? {
+                        return Ok(result);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn input(&mut self, input: i64) {
+        unwrap(self.try_input(input))
+    }
+
+    pub fn try_input(&mut self, input: i64) -> Result<(), CpuError> {
+        let rbo = self.cpu.rbo;
+        let instruction = self.cpu.decode(self.cpu.pc);
+        self.cpu.try_input(input)?;
+        if let Ok(instruction) = instruction {
+            self.written(&instruction, rbo);
+        }
+        Ok(())
+    }
+
+    /// Runs the instruction at `pc` in the interpreter
+    fn interpret(&mut self) -> Result<Option<CpuResult>, CpuError> {
+        let rbo = self.cpu.rbo;
+        let step = self.cpu.step()?;
+        self.written(&step.instruction, rbo);
+        Ok(step.result)
+    }
+
+    /// Throws away compiled code if the instruction wrote over some of it.
+    /// There's no telling what a custom opcode wrote, so those always do.
+    fn written(&mut self, instruction: &Instruction, rbo: i64) {
+        if let Instruction::Custom { .. } = instruction {
+            self.invalidate();
+            return;
+        }
+        let addr = match instruction.destination() {
+            Some(Operand::Position(addr)) => addr,
+            Some(Operand::Relative(offset)) => rbo.wrapping_add(offset),
+            _ => return,
+        };
+        if addr >= 0 && self.code.get(addr as usize) == Some(&1) {
+            self.invalidate();
+        }
+    }
+
+    /// Returns null if there's no valid instruction at `pc`
+    fn compile(&mut self, pc: usize) -> *const u8 {
+        if self.pages.len() - self.end < MAX_BLOCK_BYTES {
+            self.invalidate();
+        }
+        let origin = self.end;
+        let code = compile::block(&self.prelude, &self.cpu.memory, &mut self.code, pc, origin);
+        let code = match code {
+            Some(code) => code,
+            None => return std::ptr::null(),
+        };
+        self.pages.write()[origin..origin + code.len()].copy_from_slice(&code);
+        self.end += code.len();
+
+        // Safety: `origin` is within the pages
+        let block = unsafe { self.pages.base().add(origin) };
+        self.table[pc] = block;
+        block
+    }
+
+    fn invalidate(&mut self) {
+        for block in &mut self.table {
+            *block = std::ptr::null();
+        }
+        for word in &mut self.code {
+            *word = 0;
+        }
+        self.end = self.prelude.code.len();
+    }
+}
@@ -0,0 +1,83 @@
+//! Anonymous mmap'd pages which are either writable or executable, never both.
+
+use std::ffi::c_void;
+use std::io;
+
+const PROT_READ: i32 = 1;
+const PROT_WRITE: i32 = 2;
+const PROT_EXEC: i32 = 4;
+const MAP_PRIVATE: i32 = 2;
+const MAP_ANONYMOUS: i32 = 0x20;
+
+extern "C" {
+    fn mmap(addr: *mut c_void, len: usize, prot: i32, flags: i32, fd: i32, off: i64)
+        -> *mut c_void;
+    fn mprotect(addr: *mut c_void, len: usize, prot: i32) -> i32;
+    fn munmap(addr: *mut c_void, len: usize) -> i32;
+}
+
+pub struct Pages {
+    base: *mut u8,
+    len: usize,
+    writable: bool,
+}
+
+impl Pages {
+    pub fn new(len: usize) -> io::Result<Self> {
+        let prot = PROT_READ | PROT_WRITE;
+        let flags = MAP_PRIVATE | MAP_ANONYMOUS;
+        // Safety: a fresh anonymous mapping doesn't alias anything
+        let base = unsafe { mmap(std::ptr::null_mut(), len, prot, flags, -1, 0) };
+        if base as isize == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Pages {
+            base: base as *mut u8,
+            len,
+            writable: true,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn base(&self) -> *const u8 {
+        self.base
+    }
+
+    /// The pages as a slice to write code into
+    pub fn write(&mut self) -> &mut [u8] {
+        if !self.writable {
+            self.protect(PROT_READ | PROT_WRITE);
+            self.writable = true;
+        }
+        // Safety: the mapping is `len` bytes long and only borrowed through self
+        unsafe { std::slice::from_raw_parts_mut(self.base, self.len) }
+    }
+
+    /// Switches to executable before running anything in the pages
+    pub fn executable(&mut self) {
+        if self.writable {
+            self.protect(PROT_READ | PROT_EXEC);
+            self.writable = false;
+        }
+    }
+
+    fn protect(&mut self, prot: i32) {
+        // Safety: changes the protection of our own mapping
+        let result = unsafe { mprotect(self.base as *mut c_void, self.len, prot) };
+        if result != 0 {
+            panic!("mprotect failed: {}", io::Error::last_os_error());
+        }
+    }
+}
+
+impl Drop for Pages {
+    fn drop(&mut self) {
+        // Safety: nothing points into the mapping once its owner is gone
+        unsafe {
+            munmap(self.base as *mut c_void, self.len);
+        }
+    }
+}
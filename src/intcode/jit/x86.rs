@@ -0,0 +1,219 @@
+//! Just enough of an x86-64 encoder for the JIT. Code is assembled into a
+//! `Vec` and copied into the executable pages at `origin`, which is what jump
+//! targets are relative to.
+
+pub const RAX: u8 = 0;
+pub const RCX: u8 = 1;
+pub const RBX: u8 = 3;
+pub const RBP: u8 = 5;
+pub const RSI: u8 = 6;
+pub const RDI: u8 = 7;
+pub const R12: u8 = 12;
+pub const R13: u8 = 13;
+pub const R14: u8 = 14;
+pub const R15: u8 = 15;
+
+/// Condition codes, as used by `jcc` and `setcc`
+pub const CC_O: u8 = 0x0;
+pub const CC_AE: u8 = 0x3;
+pub const CC_E: u8 = 0x4;
+pub const CC_NE: u8 = 0x5;
+pub const CC_L: u8 = 0xc;
+
+#[derive(Copy, Clone)]
+pub struct Mem {
+    pub base: u8,
+    /// Index register and log2 of its scale
+    pub index: Option<(u8, u8)>,
+    pub disp: i32,
+}
+
+impl Mem {
+    pub fn at(base: u8, disp: i32) -> Self {
+        Mem {
+            base,
+            index: None,
+            disp,
+        }
+    }
+
+    /// `[base + index * 8]`
+    pub fn words(base: u8, index: u8) -> Self {
+        Mem {
+            base,
+            index: Some((index, 3)),
+            disp: 0,
+        }
+    }
+
+    /// `[base + index]`
+    pub fn bytes(base: u8, index: u8) -> Self {
+        Mem {
+            base,
+            index: Some((index, 0)),
+            disp: 0,
+        }
+    }
+}
+
+pub struct Assembler {
+    pub code: Vec<u8>,
+    origin: usize,
+}
+
+impl Assembler {
+    pub fn new(origin: usize) -> Self {
+        Assembler {
+            code: Vec::new(),
+            origin,
+        }
+    }
+
+    /// Offset in the pages of the next instruction
+    pub fn here(&self) -> usize {
+        self.origin + self.code.len()
+    }
+
+    pub fn push(&mut self, reg: u8) {
+        self.rex(false, 0, 0, reg);
+        self.code.push(0x50 + (reg & 7));
+    }
+
+    pub fn pop(&mut self, reg: u8) {
+        self.rex(false, 0, 0, reg);
+        self.code.push(0x58 + (reg & 7));
+    }
+
+    pub fn ret(&mut self) {
+        self.code.push(0xc3);
+    }
+
+    pub fn load(&mut self, dst: u8, mem: Mem) {
+        self.mem_op(&[0x8b], dst, mem);
+    }
+
+    pub fn store(&mut self, mem: Mem, src: u8) {
+        self.mem_op(&[0x89], src, mem);
+    }
+
+    /// Stores a sign-extended 32 bit immediate
+    pub fn store_imm(&mut self, mem: Mem, imm: i32) {
+        self.mem_op(&[0xc7], 0, mem);
+        self.code.extend_from_slice(&imm.to_le_bytes());
+    }
+
+    /// `cmp byte [mem], 0`
+    pub fn cmp_byte_zero(&mut self, mem: Mem) {
+        self.mem_op_size(false, &[0x80], 7, mem);
+        self.code.push(0);
+    }
+
+    pub fn mov(&mut self, dst: u8, src: u8) {
+        self.reg_op(&[0x89], src, dst);
+    }
+
+    pub fn add(&mut self, dst: u8, src: u8) {
+        self.reg_op(&[0x01], src, dst);
+    }
+
+    pub fn imul(&mut self, dst: u8, src: u8) {
+        self.reg_op(&[0x0f, 0xaf], dst, src);
+    }
+
+    /// Sets flags from `a - b`
+    pub fn cmp(&mut self, a: u8, b: u8) {
+        self.reg_op(&[0x39], b, a);
+    }
+
+    pub fn test(&mut self, a: u8, b: u8) {
+        self.reg_op(&[0x85], b, a);
+    }
+
+    /// `rax = cc as u64`
+    pub fn set_rax(&mut self, cc: u8) {
+        self.code.extend_from_slice(&[0x0f, 0x90 + cc, 0xc0]);
+        self.code.extend_from_slice(&[0x0f, 0xb6, 0xc0]);
+    }
+
+    pub fn mov_eax(&mut self, imm: u32) {
+        self.code.push(0xb8);
+        self.code.extend_from_slice(&imm.to_le_bytes());
+    }
+
+    pub fn jmp_reg(&mut self, reg: u8) {
+        self.rex(false, 0, 0, reg);
+        self.code.extend_from_slice(&[0xff, 0xe0 + (reg & 7)]);
+    }
+
+    pub fn jmp(&mut self, target: usize) {
+        self.code.push(0xe9);
+        self.rel32(target);
+    }
+
+    pub fn jcc(&mut self, cc: u8, target: usize) {
+        self.code.extend_from_slice(&[0x0f, 0x80 + cc]);
+        self.rel32(target);
+    }
+
+    /// A conditional jump to a target that isn't known yet, returning the
+    /// position to give `patch`
+    pub fn jcc_forward(&mut self, cc: u8) -> usize {
+        self.code.extend_from_slice(&[0x0f, 0x80 + cc, 0, 0, 0, 0]);
+        self.code.len() - 4
+    }
+
+    pub fn patch(&mut self, at: usize, target: usize) {
+        let rel = target as i64 - (self.origin + at + 4) as i64;
+        self.code[at..at + 4].copy_from_slice(&(rel as i32).to_le_bytes());
+    }
+
+    fn rel32(&mut self, target: usize) {
+        let rel = target as i64 - (self.here() + 4) as i64;
+        self.code.extend_from_slice(&(rel as i32).to_le_bytes());
+    }
+
+    fn rex(&mut self, wide: bool, reg: u8, index: u8, base: u8) {
+        let rex = 0x40 | (wide as u8) << 3 | (reg >> 3) << 2 | (index >> 3) << 1 | (base >> 3);
+        if rex != 0x40 {
+            self.code.push(rex);
+        }
+    }
+
+    fn reg_op(&mut self, opcode: &[u8], reg: u8, rm: u8) {
+        self.rex(true, reg, 0, rm);
+        self.code.extend_from_slice(opcode);
+        self.code.push(0xc0 | (reg & 7) << 3 | (rm & 7));
+    }
+
+    fn mem_op(&mut self, opcode: &[u8], reg: u8, mem: Mem) {
+        self.mem_op_size(true, opcode, reg, mem);
+    }
+
+    fn mem_op_size(&mut self, wide: bool, opcode: &[u8], reg: u8, mem: Mem) {
+        let index = mem.index.map_or(0, |(index, _)| index);
+        self.rex(wide, reg, index, mem.base);
+        self.code.extend_from_slice(opcode);
+
+        // rbp and r13 can't be used as a base without a displacement
+        let (mode, disp) = match mem.disp {
+            0 if mem.base & 7 != RBP => (0, 0),
+            disp if disp as i8 as i32 == disp => (1, 1),
+            _ => (2, 4),
+        };
+        match mem.index {
+            Some((index, scale)) => {
+                self.code.push(mode << 6 | (reg & 7) << 3 | 4);
+                self.code
+                    .push(scale << 6 | (index & 7) << 3 | (mem.base & 7));
+            }
+            // rsp and r12 always need a SIB byte
+            None if mem.base & 7 == 4 => {
+                self.code.push(mode << 6 | (reg & 7) << 3 | 4);
+                self.code.push(0x24);
+            }
+            None => self.code.push(mode << 6 | (reg & 7) << 3 | (mem.base & 7)),
+        }
+        let bytes = mem.disp.to_le_bytes();
+        self.code.extend_from_slice(&bytes[..disp]);
+    }
+}
@@ -0,0 +1,148 @@
+//! Memory backends for `Cpu`. A plain `Vec` is the fast default and
+//! grows to cover the highest address written, up to `VEC_LIMIT`.
+//! `PagedMemory` only allocates the pages that are actually written, for
+//! programs which use huge addresses.
+
+use super::Word;
+
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::iter::FromIterator;
+
+/// Words per page of `PagedMemory`
+pub const PAGE_SIZE: usize = 1024;
+
+/// Addresses a `Vec` can grow to hold. Writing past it is an
+/// `ErrorKind::AddressOverflow` rather than a multi-gigabyte allocation.
+pub const VEC_LIMIT: usize = 1 << 24;
+
+pub trait Memory<W = i64> {
+    /// Reads `addr`, which is zero if it's never been written
+    fn read(&self, addr: usize) -> W;
+
+    /// Writes `addr`, which is always below `limit`
+    fn write(&mut self, addr: usize, value: W);
+
+    /// One past the highest address that can be written
+    fn limit(&self) -> usize {
+        usize::MAX
+    }
+
+    /// One past the highest address that may be non-zero
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Feeds every word to `state`, for fingerprinting the whole memory
+    fn hash_words<H: Hasher>(&self, state: &mut H)
+    where
+        W: Hash,
+    {
+        for addr in 0..self.len() {
+            self.read(addr).hash(state);
+        }
+    }
+}
+
+impl<W: Word> Memory<W> for Vec<W> {
+    #[inline(always)]
+    fn read(&self, addr: usize) -> W {
+        self.get(addr).cloned().unwrap_or_else(W::zero)
+    }
+
+    #[inline(always)]
+    fn write(&mut self, addr: usize, value: W) {
+        if addr >= self.len() {
+            self.resize(addr + 1, W::zero());
+        }
+        self[addr] = value;
+    }
+
+    fn limit(&self) -> usize {
+        VEC_LIMIT
+    }
+
+    fn len(&self) -> usize {
+        Vec::len(self)
+    }
+
+    fn hash_words<H: Hasher>(&self, state: &mut H) {
+        self.hash(state);
+    }
+}
+
+/// Sparse memory made of fixed size pages, allocated on first write
+#[derive(Clone, Debug)]
+pub struct PagedMemory<W = i64> {
+    pages: HashMap<usize, Box<[W]>>,
+    len: usize,
+}
+
+impl<W> Default for PagedMemory<W> {
+    fn default() -> Self {
+        PagedMemory {
+            pages: HashMap::new(),
+            len: 0,
+        }
+    }
+}
+
+impl<W> PagedMemory<W> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of pages allocated so far
+    pub fn pages(&self) -> usize {
+        self.pages.len()
+    }
+}
+
+impl<W: Word> Memory<W> for PagedMemory<W> {
+    fn read(&self, addr: usize) -> W {
+        match self.pages.get(&(addr / PAGE_SIZE)) {
+            Some(page) => page[addr % PAGE_SIZE].clone(),
+            None => W::zero(),
+        }
+    }
+
+    fn write(&mut self, addr: usize, value: W) {
+        let page = self
+            .pages
+            .entry(addr / PAGE_SIZE)
+            .or_insert_with(|| vec![W::zero(); PAGE_SIZE].into_boxed_slice());
+        page[addr % PAGE_SIZE] = value;
+        self.len = self.len.max(addr + 1);
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn hash_words<H: Hasher>(&self, state: &mut H) {
+        let mut pages: Vec<_> = self.pages.iter().collect();
+        pages.sort_unstable_by_key(|&(&index, _)| index);
+        for (index, page) in pages {
+            index.hash(state);
+            page.hash(state);
+        }
+    }
+}
+
+impl<W: Word> From<Vec<W>> for PagedMemory<W> {
+    fn from(words: Vec<W>) -> Self {
+        words.into_iter().collect()
+    }
+}
+
+impl<W: Word> FromIterator<W> for PagedMemory<W> {
+    fn from_iter<I: IntoIterator<Item = W>>(iter: I) -> Self {
+        let mut memory = PagedMemory::new();
+        for (addr, word) in iter.into_iter().enumerate() {
+            memory.write(addr, word);
+        }
+        memory
+    }
+}
@@ -1,8 +1,30 @@
-pub use self::cpu::{Cpu, CpuResult};
-pub use self::io::{ChannelIO, SingleIO, StdIO, IO};
+pub use self::asyncio::{AsyncChannelIO, AsyncIO};
+pub use self::cpu::{
+    Budget, Cpu, CpuError, CpuResult, ErrorKind, Extension, Instruction, Operand, Outputs, Step,
+};
+pub use self::io::{AsciiIO, ChannelIO, Exit, Flow, Input, SingleIO, StdIO, IO};
+pub use self::memory::{Memory, PagedMemory};
+pub use self::word::{parse_words, Arithmetic, Word};
 pub use crate::parse::parse_i64_vec as parse;
+pub use intcode_macros::intcode_fn;
 
+pub mod asm;
+pub mod asyncio;
+pub mod circuit;
+pub mod compiled;
 pub mod cpu;
+pub mod debugger;
+pub mod disasm;
+pub mod executor;
+pub mod hooks;
 pub mod io;
+#[cfg(feature = "jit")]
+pub mod jit;
+pub mod memory;
+pub mod network;
+pub mod profile;
 #[cfg(test)]
 mod tests;
+pub mod threaded;
+pub mod trace;
+pub mod word;
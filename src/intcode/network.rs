@@ -0,0 +1,207 @@
+//! Runs copies of a program as machines on a network. Each machine is given
+//! its address as its first input, then sends packets by outputting
+//! `dest, x, y` and receives them as `x, y` inputs, reading -1 whenever its
+//! queue is empty.
+//!
+//! A NAT address can be set up. Packets sent to it are held, and once the
+//! network goes idle the last one is sent on to address 0 to wake it.
+
+use super::{Cpu, CpuError, CpuResult};
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::rc::Rc;
+
+/// Packets a machine can send in one round before the next machine gets a
+/// turn, so one that never reads its input can't hold up the rest
+pub const ROUND_PACKETS: usize = 64;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Packet {
+    pub src: i64,
+    pub dest: i64,
+    pub x: i64,
+    pub y: i64,
+}
+
+/// Observes the traffic on a `Network`, see `Network::add_hook`
+#[allow(unused_variables)]
+pub trait NetworkHook {
+    /// A machine sent a packet, whether or not anything is at `dest`
+    fn packet(&mut self, packet: &Packet) {}
+
+    /// The network went idle and the NAT sent `packet` to wake it
+    fn wake(&mut self, packet: &Packet) {}
+}
+
+impl<H: NetworkHook> NetworkHook for Rc<RefCell<H>> {
+    fn packet(&mut self, packet: &Packet) {
+        self.borrow_mut().packet(packet)
+    }
+
+    fn wake(&mut self, packet: &Packet) {
+        self.borrow_mut().wake(packet)
+    }
+}
+
+struct Machine {
+    cpu: Cpu,
+    queue: VecDeque<i64>,
+    /// Outputs of a packet that's still being sent
+    sending: Vec<i64>,
+    halted: bool,
+}
+
+struct Nat {
+    address: i64,
+    last: Option<Packet>,
+}
+
+pub struct Network {
+    machines: Vec<Machine>,
+    nat: Option<Nat>,
+    hooks: Vec<Box<dyn NetworkHook>>,
+    idle: bool,
+}
+
+impl Network {
+    /// Boots `size` copies of `program` with addresses `0..size`
+    pub fn new(program: &[i64], size: usize) -> Self {
+        let machines = (0..size)
+            .map(|address| Machine {
+                cpu: Cpu::new(program.to_vec()),
+                queue: VecDeque::from(vec![address as i64]),
+                sending: Vec::with_capacity(3),
+                halted: false,
+            })
+            .collect();
+        Network {
+            machines,
+            nat: None,
+            hooks: Vec::new(),
+            idle: false,
+        }
+    }
+
+    /// Puts a NAT at `address`
+    pub fn with_nat(mut self, address: i64) -> Self {
+        self.nat = Some(Nat {
+            address,
+            last: None,
+        });
+        self
+    }
+
+    pub fn add_hook(&mut self, hook: impl NetworkHook + 'static) {
+        self.hooks.push(Box::new(hook));
+    }
+
+    /// The packet the NAT is holding on to
+    pub fn nat_packet(&self) -> Option<Packet> {
+        self.nat.as_ref().and_then(|nat| nat.last)
+    }
+
+    /// Whether nothing was sent in the last round
+    pub fn idle(&self) -> bool {
+        self.idle
+    }
+
+    pub fn halted(&self) -> bool {
+        self.machines.iter().all(|machine| machine.halted)
+    }
+
+    /// Runs every machine until it halts, waits on an empty queue for the
+    /// second time or has sent `ROUND_PACKETS` packets. If nothing was sent
+    /// the network is idle, and the NAT wakes it with the packet it returns.
+    ///
+    /// A machine that spins without doing any I/O never gives up its turn.
+    pub fn round(&mut self) -> Result<Option<Packet>, CpuError> {
+        let mut sent = 0;
+        for address in 0..self.machines.len() {
+            for _ in 0..ROUND_PACKETS {
+                match self.run_machine(address)? {
+                    Some(packet) => {
+                        sent += 1;
+                        self.route(packet);
+                    }
+                    None => break,
+                }
+            }
+        }
+
+        self.idle = sent == 0 && self.machines.iter().all(|m| m.queue.is_empty());
+        if !self.idle {
+            return Ok(None);
+        }
+        let packet = match self.nat_packet() {
+            Some(packet) if !self.machines.is_empty() => Packet {
+                src: packet.dest,
+                dest: 0,
+                ..packet
+            },
+            _ => return Ok(None),
+        };
+        for hook in &mut self.hooks {
+            hook.wake(&packet);
+        }
+        self.machines[0].queue.extend(&[packet.x, packet.y]);
+        Ok(Some(packet))
+    }
+
+    /// Runs rounds until every machine has halted, or the network goes idle
+    /// with nothing for the NAT to send. A NAT can keep the network going
+    /// forever, in which case call `round` instead.
+    pub fn run(&mut self) -> Result<(), CpuError> {
+        loop {
+            let woke = self.round()?;
+            if self.halted() || (self.idle && woke.is_none()) {
+                break Ok(());
+            }
+        }
+    }
+
+    /// Runs the machine until it sends a packet or has nothing more to do
+    fn run_machine(&mut self, address: usize) -> Result<Option<Packet>, CpuError> {
+        let machine = &mut self.machines[address];
+        let mut starved = false;
+        while !machine.halted {
+            match machine.cpu.try_resume()? {
+                CpuResult::Halt => machine.halted = true,
+                CpuResult::Input => match machine.queue.pop_front() {
+                    Some(value) => machine.cpu.try_input(value)?,
+                    None if starved => break,
+                    None => {
+                        starved = true;
+                        machine.cpu.try_input(-1)?;
+                    }
+                },
+                CpuResult::Output(value) => {
+                    machine.sending.push(value);
+                    if let [dest, x, y] = machine.sending[..] {
+                        machine.sending.clear();
+                        let src = address as i64;
+                        return Ok(Some(Packet { src, dest, x, y }));
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    fn route(&mut self, packet: Packet) {
+        for hook in &mut self.hooks {
+            hook.packet(&packet);
+        }
+        match &mut self.nat {
+            Some(nat) if nat.address == packet.dest => nat.last = Some(packet),
+            _ => {
+                let dest = usize::try_from(packet.dest).ok();
+                // Packets to nowhere are dropped
+                if let Some(machine) = dest.and_then(|dest| self.machines.get_mut(dest)) {
+                    machine.queue.extend(&[packet.x, packet.y]);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use super::Instruction;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Write};
+
+const TOP: usize = 10;
+
+#[derive(Clone, Debug, Default)]
+pub struct Profile {
+    pub total: u64,
+    /// Instructions executed, indexed by opcode
+    pub opcodes: HashMap<i64, u64>,
+    /// Instructions executed, keyed by address. Sparse, since programs can
+    /// run code anywhere in memory.
+    pub addresses: HashMap<usize, u64>,
+    /// Backward jumps taken, keyed by `(target, jump address)`. Each one is
+    /// an iteration of the loop spanning those addresses.
+    pub loops: HashMap<(usize, usize), u64>,
+    /// Instructions executed between the cpu starting and returning with I/O
+    /// or a halt. `resume_for` slices which run out of budget carry on the
+    /// same entry.
+    pub resumes: Vec<u64>,
+    /// Whether the last entry in `resumes` is still being counted
+    resuming: bool,
+}
+
+impl Profile {
+    pub(crate) fn begin_resume(&mut self) {
+        if !self.resuming {
+            self.resumes.push(0);
+            self.resuming = true;
+        }
+    }
+
+    pub(crate) fn end_resume(&mut self) {
+        self.resuming = false;
+    }
+
+    pub(crate) fn record<W: Clone>(
+        &mut self,
+        pc: usize,
+        instruction: &Instruction<W>,
+        next_pc: usize,
+    ) {
+        self.total += 1;
+        *self.opcodes.entry(instruction.opcode()).or_insert(0) += 1;
+        *self.addresses.entry(pc).or_insert(0) += 1;
+        if let Some(count) = self.resumes.last_mut() {
+            *count += 1;
+        }
+
+        if let Instruction::Jnz(..) | Instruction::Jz(..) = instruction {
+            if next_pc <= pc {
+                *self.loops.entry((next_pc, pc)).or_insert(0) += 1;
+            }
+        }
+    }
+
+    pub fn hottest_addresses(&self) -> Vec<(usize, u64)> {
+        let mut hits: Vec<_> = self.addresses.iter().map(|(&addr, &n)| (addr, n)).collect();
+        hits.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        hits
+    }
+
+    pub fn hottest_loops(&self) -> Vec<((usize, usize), u64)> {
+        let mut loops: Vec<_> = self.loops.iter().map(|(&span, &n)| (span, n)).collect();
+        loops.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        loops
+    }
+
+    /// Writes every statistic as `kind,key,count` rows
+    pub fn write_csv(&self, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, "kind,key,count")?;
+        writeln!(out, "total,,{}", self.total)?;
+
+        let mut opcodes: Vec<_> = self.opcodes.iter().collect();
+        opcodes.sort();
+        for (op, count) in opcodes {
+            writeln!(out, "opcode,{},{}", op, count)?;
+        }
+        let mut addresses: Vec<_> = self.addresses.iter().collect();
+        addresses.sort();
+        for (addr, count) in addresses {
+            writeln!(out, "address,{},{}", addr, count)?;
+        }
+        for ((start, end), count) in self.hottest_loops() {
+            writeln!(out, "loop,{}-{},{}", start, end, count)?;
+        }
+        for (i, count) in self.resumes.iter().enumerate() {
+            writeln!(out, "resume,{},{}", i, count)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let percent = |n: u64| 100.0 * n as f64 / self.total.max(1) as f64;
+
+        writeln!(f, "Instructions executed: {}", self.total)?;
+
+        writeln!(f, "\nBy opcode:")?;
+        let mut opcodes: Vec<_> = self.opcodes.iter().map(|(&op, &n)| (op, n)).collect();
+        opcodes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        for (op, count) in opcodes {
+            writeln!(f, "  {:>4} {:>12} {:>6.2}%", op, count, percent(count))?;
+        }
+
+        writeln!(f, "\nHottest addresses:")?;
+        for (addr, count) in self.hottest_addresses().into_iter().take(TOP) {
+            writeln!(f, "  {:>6} {:>12} {:>6.2}%", addr, count, percent(count))?;
+        }
+
+        writeln!(f, "\nHottest loops:")?;
+        for ((start, end), count) in self.hottest_loops().into_iter().take(TOP) {
+            let span = format!("{}-{}", start, end);
+            writeln!(f, "  {:>13} {:>12} iterations", span, count)?;
+        }
+
+        if !self.resumes.is_empty() {
+            let min = self.resumes.iter().min().unwrap();
+            let max = self.resumes.iter().max().unwrap();
+            let sum: u64 = self.resumes.iter().sum();
+            writeln!(f, "\nResumes: {}", self.resumes.len())?;
+            writeln!(
+                f,
+                "  instructions per resume: min {}, mean {:.1}, max {}",
+                min,
+                sum as f64 / self.resumes.len() as f64,
+                max
+            )?;
+        }
+        Ok(())
+    }
+}
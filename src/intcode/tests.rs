@@ -8,3 +8,1269 @@ fn test_parse() {
     assert_eq!(parse("-1,"), vec![-1]);
     assert_eq!(parse("-1\n2,3,4"), vec![-1, 2, 3, 4]);
 }
+
+#[cfg(test)]
+#[test]
+fn test_unknown_opcode() {
+    use super::{Cpu, ErrorKind};
+
+    let mut cpu = Cpu::new(vec![1, 0, 0, 0, 42]);
+    let err = cpu.try_resume().unwrap_err();
+    assert_eq!(err.pc, 4);
+    assert_eq!(err.instruction, 42);
+    assert_eq!(err.kind, ErrorKind::UnknownOpcode(42));
+}
+
+#[cfg(test)]
+#[test]
+fn test_negative_address() {
+    use super::{Cpu, ErrorKind};
+
+    let mut cpu = Cpu::new(vec![1, -5, 0, 0, 99]);
+    let err = cpu.try_resume().unwrap_err();
+    assert_eq!(err.pc, 0);
+    assert_eq!(err.kind, ErrorKind::NegativeAddress(-5));
+
+    let mut cpu = Cpu::new(vec![1105, 1, -1]);
+    let err = cpu.try_resume().unwrap_err();
+    assert_eq!(err.kind, ErrorKind::NegativeAddress(-1));
+}
+
+#[cfg(test)]
+#[test]
+fn test_step() {
+    use super::{Cpu, CpuResult, Instruction, Operand::*};
+
+    let mut cpu = Cpu::new(vec![1001, 5, 2, 5, 104, 7, 99]);
+    assert_eq!(
+        cpu.decode(0).unwrap(),
+        Instruction::Add(Position(5), Immediate(2), Position(5))
+    );
+
+    let step = cpu.step().unwrap();
+    assert_eq!(step.pc, 0);
+    assert_eq!(step.result, None);
+    assert_eq!(cpu.pc, 4);
+    assert_eq!(cpu.memory[5], 9);
+
+    let step = cpu.step().unwrap();
+    assert_eq!(step.instruction, Instruction::Out(Immediate(9)));
+    assert_eq!(step.result, Some(CpuResult::Output(9)));
+    assert_eq!(cpu.step().unwrap().result, Some(CpuResult::Halt));
+    assert_eq!(cpu.pc, 6);
+}
+
+#[cfg(test)]
+#[test]
+fn test_disassemble() {
+    use super::disasm::{disassemble, LineKind};
+
+    // Jumps over a block of data, then outputs from it and halts
+    let program = vec![1105, 1, 6, 72, 105, 10, 4, 3, 99];
+    let listing = disassemble(&program);
+
+    let kinds: Vec<_> = listing
+        .lines
+        .iter()
+        .map(|line| match line.kind {
+            LineKind::Code(instr) => (line.addr, instr.mnemonic()),
+            LineKind::Data => (line.addr, ".data"),
+        })
+        .collect();
+    assert_eq!(kinds, [(0, "jnz"), (3, ".data"), (6, "out"), (8, "hlt")]);
+
+    let text = listing.to_string();
+    assert!(text.contains("jnz #1, #6"));
+    let data = format!("{:>6}: {:<24} .data 72, 105, 10  ; \"Hi\\n\"", 3, "");
+    assert!(text.lines().any(|line| line == data));
+
+    // The jump to 4 lands inside the jump at 3
+    let listing = disassemble(&[1005, 20, 4, 1105, 1, 7, 99, 99]);
+    assert_eq!(listing.lines[1].addr, 3);
+    assert_eq!(listing.lines[1].overlaps[0].0, 4);
+    assert!(listing.to_string().contains("; overlapping: add 7, 99, 99"));
+}
+
+#[cfg(test)]
+#[test]
+fn test_assemble() {
+    use super::asm::assemble;
+    use super::{Cpu, CpuResult};
+
+    let source = r#"
+        ; Doubles each input with a subroutine until a zero is read
+                arb #stack
+        loop:   in value
+                jz value, #done
+                push value
+                call #double
+                pop value
+                out value
+                jmp #loop
+        done:   out msg+1
+                hlt
+        double: add @-2, @-2, @-2
+                ret
+        value:  .data 0
+        msg:    .data "Hi", 0
+        stack:
+    "#;
+    let mut cpu = Cpu::new(assemble(source).unwrap());
+
+    let mut inputs = vec![0, 21, 3];
+    let mut outputs = vec![];
+    loop {
+        match cpu.resume() {
+            CpuResult::Halt => break,
+            CpuResult::Input => cpu.input(inputs.pop().unwrap()),
+            CpuResult::Output(out) => outputs.push(out),
+        }
+    }
+    assert_eq!(outputs, [6, 42, 'i' as i64]);
+}
+
+#[cfg(test)]
+#[test]
+fn test_assemble_errors() {
+    use super::asm::assemble;
+
+    let err = assemble("start: add x, #1, x\n  jz #0, #nowhere").unwrap_err();
+    assert_eq!((err.line, err.column), (1, 12));
+    assert_eq!(err.message, "undefined label `x`");
+
+    let err = assemble("\n  mul #2, #3, #4").unwrap_err();
+    assert_eq!((err.line, err.column), (2, 15));
+
+    let err = assemble("  out 1 2").unwrap_err();
+    assert_eq!(err.message, "unexpected `2`");
+
+    let err = assemble("  hlt\n  .zero 99999999999").unwrap_err();
+    assert_eq!((err.line, err.column), (2, 9));
+    assert_eq!(err.message, "`.zero` would go past 16777216 words");
+
+    let err = assemble(".zero 16777216\n.data 1\n.zero 0").unwrap_err();
+    assert_eq!((err.line, err.column), (2, 1));
+    assert_eq!(err.message, "program is longer than 16777216 words");
+    assert_eq!(assemble("hlt\n.zero 16777215").unwrap().len(), 1 << 24);
+
+    let err = assemble(".start: hlt").unwrap_err();
+    assert_eq!((err.line, err.column), (1, 1));
+    assert_eq!(err.message, "label `.start` can't start with `.`");
+}
+
+#[cfg(test)]
+#[test]
+fn test_debugger() {
+    use super::debugger::{Debugger, Stop};
+    use super::{Cpu, ErrorKind};
+
+    // Reads a value into 9, doubles it and outputs it
+    let mut dbg = Debugger::new(Cpu::new(vec![3, 9, 1, 9, 9, 9, 4, 9, 99, 0]));
+    dbg.breakpoints.insert(8);
+    dbg.watch(9);
+
+    assert_eq!(dbg.cont(), Stop::NeedInput);
+    dbg.inputs.push_back(21);
+    assert_eq!(
+        dbg.cont(),
+        Stop::Watchpoint {
+            addr: 9,
+            old: 0,
+            new: 21
+        }
+    );
+    assert_eq!(
+        dbg.cont(),
+        Stop::Watchpoint {
+            addr: 9,
+            old: 21,
+            new: 42
+        }
+    );
+    assert_eq!(dbg.cont(), Stop::Breakpoint(8));
+    assert_eq!(dbg.outputs, [42]);
+    assert_eq!(dbg.step(1), Stop::Halt);
+
+    // Pokes past the end of memory are refused instead of allocated
+    assert_eq!(dbg.poke(20, 7), Ok(()));
+    assert_eq!(dbg.peek(20), 7);
+    assert_eq!(
+        dbg.poke(1 << 40, 7),
+        Err(ErrorKind::AddressOverflow(1 << 40))
+    );
+    assert_eq!(dbg.cpu.memory.len(), 21);
+}
+
+#[cfg(test)]
+#[test]
+fn test_trace() {
+    use super::trace::{read_binary, IoEvent, TraceFormat};
+    use super::Cpu;
+
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let program = vec![109, 3, 203, 8, 1001, 11, 5, 11, 4, 11, 99, 0];
+
+    let json = Shared::default();
+    let mut cpu = Cpu::new(program.clone());
+    cpu.start_trace(TraceFormat::Json, json.clone());
+    assert_eq!(cpu.compute(37), 42);
+    cpu.stop_trace().unwrap();
+
+    let text = String::from_utf8(json.0.lock().unwrap().clone()).unwrap();
+    let lines: Vec<_> = text.lines().collect();
+    assert_eq!(
+        lines,
+        [
+            r#"{"n":0,"pc":0,"op":9,"args":[[null,3]],"rbo":3}"#,
+            r#"{"n":1,"pc":2,"op":3,"args":[[11,0]],"write":[11,37],"in":37}"#,
+            r#"{"n":2,"pc":4,"op":1,"args":[[11,37],[null,5],[11,37]],"write":[11,42]}"#,
+            r#"{"n":3,"pc":8,"op":4,"args":[[11,42]],"out":42}"#,
+            r#"{"n":4,"pc":10,"op":99,"args":[]}"#,
+        ]
+    );
+
+    let binary = Shared::default();
+    let mut cpu = Cpu::new(program);
+    cpu.start_trace(TraceFormat::Binary, binary.clone());
+    cpu.compute(37);
+    cpu.stop_trace().unwrap();
+
+    let records = read_binary(&binary.0.lock().unwrap()[..]).unwrap();
+    assert_eq!(records.len(), 5);
+    assert_eq!(&records[1].writes[..], [(11, 37)]);
+    assert_eq!(records[3].io, Some(IoEvent::Output(42)));
+    assert_eq!(records[0].rbo, Some(3));
+
+    // Every write a custom opcode makes is recorded
+    let custom = || {
+        let mut cpu = Cpu::new(vec![10, 4, 5, 99, 0, 0]);
+        cpu.register_opcode(10, 2, |ext| {
+            ext.set(1, 7)?;
+            ext.set(2, 8)
+        });
+        cpu
+    };
+    let json = Shared::default();
+    let mut cpu = custom();
+    cpu.start_trace(TraceFormat::Json, json.clone());
+    cpu.run_collect(&[]);
+    cpu.stop_trace().unwrap();
+    let text = String::from_utf8(json.0.lock().unwrap().clone()).unwrap();
+    assert_eq!(
+        text.lines().next(),
+        Some(r#"{"n":0,"pc":0,"op":10,"args":[],"write":[4,7,5,8]}"#)
+    );
+
+    let binary = Shared::default();
+    let mut cpu = custom();
+    cpu.start_trace(TraceFormat::Binary, binary.clone());
+    cpu.run_collect(&[]);
+    cpu.stop_trace().unwrap();
+    let records = read_binary(&binary.0.lock().unwrap()[..]).unwrap();
+    assert_eq!(&records[0].writes[..], [(4, 7), (5, 8)]);
+
+    // Tracing doesn't change what the program does. Neither jump is taken,
+    // so their bad targets are never resolved.
+    for &(program, rbo, first) in &[
+        (
+            &[106, 1, -5, 104, 7, 99][..],
+            0,
+            r#"{"n":0,"pc":0,"op":6,"args":[[null,1]]}"#,
+        ),
+        (
+            &[2105, 0, 1, 104, 7, 99][..],
+            i64::MAX,
+            r#"{"n":0,"pc":0,"op":5,"args":[[null,0]]}"#,
+        ),
+    ] {
+        let mut plain = Cpu::new(program.to_vec());
+        plain.rbo = rbo;
+        let json = Shared::default();
+        let mut traced = Cpu::new(program.to_vec());
+        traced.rbo = rbo;
+        traced.start_trace(TraceFormat::Json, json.clone());
+        assert_eq!(traced.try_resume(), plain.try_resume());
+        traced.stop_trace().unwrap();
+        let text = String::from_utf8(json.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(text.lines().next(), Some(first));
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_save_load() {
+    use super::{Arithmetic, Cpu, CpuResult};
+
+    // Adds two inputs together
+    let mut cpu = Cpu::new(vec![3, 11, 3, 12, 1, 11, 12, 11, 4, 11, 99]);
+    assert_eq!(cpu.resume(), CpuResult::Input);
+    cpu.input(40);
+    assert_eq!(cpu.resume(), CpuResult::Input);
+    cpu.rbo = -3;
+    cpu.arithmetic = Arithmetic::Saturating;
+
+    let mut saved = Vec::new();
+    cpu.save(&mut saved).unwrap();
+
+    let mut restored = Cpu::load(&saved[..]).unwrap();
+    assert_eq!(restored.memory, cpu.memory);
+    assert_eq!((restored.pc, restored.rbo), (2, -3));
+    assert_eq!(restored.arithmetic, Arithmetic::Saturating);
+    restored.input(2);
+    assert_eq!(restored.resume(), CpuResult::Output(42));
+
+    saved[0] = b'X';
+    assert!(Cpu::load(&saved[..]).is_err());
+    assert!(Cpu::load(&[][..]).is_err());
+}
+
+#[cfg(test)]
+#[test]
+fn test_profile() {
+    use super::{Cpu, CpuResult, PagedMemory};
+
+    // Counts down from the input, outputting each value
+    let mut cpu = Cpu::new(vec![3, 12, 4, 12, 1001, 12, -1, 12, 1005, 12, 2, 99, 0]);
+    cpu.start_profile();
+    assert_eq!(cpu.resume(), CpuResult::Input);
+    cpu.input(3);
+    let mut outputs = vec![];
+    while let CpuResult::Output(out) = cpu.resume() {
+        outputs.push(out);
+    }
+    assert_eq!(outputs, [3, 2, 1]);
+
+    let profile = cpu.stop_profile().unwrap();
+    assert_eq!(profile.total, 11);
+    assert_eq!(profile.opcodes[&4], 3);
+    assert_eq!(profile.addresses[&2], 3);
+    assert_eq!(profile.hottest_loops(), [((2, 8), 2)]);
+    assert_eq!(profile.resumes, [1, 1, 3, 3, 3]);
+    assert!(cpu.profile().is_none());
+
+    let mut csv = Vec::new();
+    profile.write_csv(&mut csv).unwrap();
+    let csv = String::from_utf8(csv).unwrap();
+    assert!(csv.starts_with("kind,key,count\ntotal,,11\n"));
+    assert!(csv.contains("\nloop,2-8,2\n"));
+
+    // Stores a halt a trillion words in and jumps to it
+    let far: i64 = 1_000_000_000_000;
+    let mut cpu = Cpu::with_memory(PagedMemory::from(vec![1101, 99, 0, far, 1105, 1, far]));
+    cpu.start_profile();
+    assert_eq!(cpu.resume(), CpuResult::Halt);
+    let profile = cpu.stop_profile().unwrap();
+    assert_eq!(profile.addresses.len(), 3);
+    assert_eq!(profile.hottest_addresses()[2], (far as usize, 1));
+}
+
+#[cfg(test)]
+#[test]
+fn test_threaded_matches_cpu() {
+    use super::threaded::ThreadedCpu;
+    use super::{parse, Cpu, ErrorKind};
+
+    let day05 = parse(include_str!("../bin/input/day05.txt"));
+    let day09 = parse(include_str!("../bin/input/day09.txt"));
+    for &(program, input) in &[(&day05, 1), (&day05, 5), (&day09, 1), (&day09, 2)] {
+        let expected = Cpu::new(program.clone()).compute(input);
+        assert_eq!(ThreadedCpu::new(program.clone()).compute(input), expected);
+    }
+
+    // Overwrites the add at 4 with a multiply before running it
+    let program = vec![1101, 0, 2, 4, 1, 11, 12, 11, 4, 11, 99, 6, 7];
+    let mut cpu = ThreadedCpu::new(program.clone());
+    assert_eq!(cpu.compute(0), 42);
+    assert_eq!(cpu.into_cpu().memory, {
+        let mut cpu = Cpu::new(program);
+        cpu.compute(0);
+        cpu.memory
+    });
+
+    // Rewrites the output instruction in a loop that has already decoded it
+    let program = vec![4, 9, 1001, 1, 1, 1, 1005, 10, 0, 99, 1];
+    let mut threaded = ThreadedCpu::new(program.clone());
+    let mut cpu = Cpu::new(program);
+    for _ in 0..4 {
+        assert_eq!(threaded.try_resume(), cpu.try_resume());
+    }
+
+    // A destination in mode 3 is relative, mode 4 isn't anything
+    for &program in &[&[30001, 5, 6, 0, 99, 7, 8], &[40001, 5, 6, 0, 99, 7, 8]] {
+        let mut threaded = ThreadedCpu::new(program.to_vec());
+        let mut cpu = Cpu::new(program.to_vec());
+        assert_eq!(threaded.try_resume(), cpu.try_resume());
+        assert_eq!(threaded.into_cpu().memory, cpu.memory);
+    }
+
+    // Relative addresses past i64::MAX overflow rather than wrapping around
+    for program in &[vec![204, 1, 99], vec![21101, 1, 2, 1, 99]] {
+        let mut threaded = ThreadedCpu::new(program.clone());
+        let mut cpu = Cpu::new(program.clone());
+        threaded.rbo = i64::MAX;
+        cpu.rbo = i64::MAX;
+        let err = threaded.try_resume().unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Overflow);
+        assert_eq!(Err(err), cpu.try_resume());
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_compiled_matches_cpu() {
+    use super::{intcode_fn, parse, Cpu, CpuResult};
+
+    for &input in &[1, 2] {
+        let expected = Cpu::new(parse(include_str!("../bin/input/day09.txt"))).compute(input);
+        let mut compiled = intcode_fn!("src/bin/input/day09.txt");
+        assert_eq!(compiled.compute(input), expected);
+        assert!(!compiled.is_interpreted());
+    }
+    for &input in &[1, 5] {
+        let expected = Cpu::new(parse(include_str!("../bin/input/day05.txt"))).compute(input);
+        let mut compiled = intcode_fn!("src/bin/input/day05.txt");
+        assert_eq!(compiled.compute(input), expected);
+    }
+
+    // Overwrites the add at 4 with a multiply before running it
+    let mut compiled = intcode_fn!("src/intcode/testdata/overwrite.txt");
+    let mut cpu = Cpu::new(parse(include_str!("testdata/overwrite.txt")));
+    assert_eq!(compiled.compute(0), cpu.compute(0));
+    assert!(compiled.is_interpreted());
+    assert_eq!(compiled.memory(), &cpu.memory[..]);
+
+    // Rewrites the output instruction's operand from a loop, which the
+    // compiled code can handle by itself
+    let mut compiled = intcode_fn!("src/intcode/testdata/rewrite_loop.txt");
+    let mut cpu = Cpu::new(parse(include_str!("testdata/rewrite_loop.txt")));
+    for _ in 0..4 {
+        assert_eq!(compiled.try_resume(), cpu.try_resume());
+    }
+    assert!(!compiled.is_interpreted());
+
+    let mut compiled = intcode_fn!("src/intcode/testdata/negative_address.txt");
+    let mut cpu = Cpu::new(parse(include_str!("testdata/negative_address.txt")));
+    assert_eq!(compiled.try_resume(), cpu.try_resume());
+    assert_eq!(compiled.pc(), 2);
+
+    let mut compiled = intcode_fn!("src/intcode/testdata/far_write.txt");
+    let mut cpu = Cpu::new(parse(include_str!("testdata/far_write.txt")));
+    assert_eq!(compiled.try_resume(), cpu.try_resume());
+
+    let mut compiled = intcode_fn!("src/intcode/testdata/relative_overflow.txt");
+    let mut cpu = Cpu::new(parse(include_str!("testdata/relative_overflow.txt")));
+    assert_eq!(compiled.try_resume(), cpu.try_resume());
+
+    // Patching memory hands over to the interpreter
+    let mut compiled = intcode_fn!("src/intcode/testdata/rewrite_loop.txt");
+    compiled.memory_mut()[0] = 99;
+    assert_eq!(compiled.resume(), CpuResult::Halt);
+    assert_eq!(compiled.into_cpu().pc, 0);
+}
+
+#[cfg(feature = "jit")]
+#[test]
+fn test_jit_matches_cpu() {
+    use super::jit::JitCpu;
+    use super::{parse, Arithmetic, Cpu, CpuResult};
+
+    let days = [
+        include_str!("../bin/input/day02.txt"),
+        include_str!("../bin/input/day05.txt"),
+        include_str!("../bin/input/day07.txt"),
+        include_str!("../bin/input/day09.txt"),
+        include_str!("../bin/input/day11.txt"),
+        include_str!("../bin/input/day13.txt"),
+        include_str!("../bin/input/day15.txt"),
+        include_str!("testdata/overwrite.txt"),
+        include_str!("testdata/rewrite_loop.txt"),
+        include_str!("testdata/negative_address.txt"),
+        include_str!("testdata/far_write.txt"),
+        include_str!("testdata/relative_overflow.txt"),
+    ];
+    for program in days.iter().map(|day| parse(day)) {
+        let mut jit = JitCpu::new(program.clone());
+        let mut cpu = Cpu::new(program);
+        // Feeds both the same arbitrary inputs until they stop
+        let mut inputs = (0..).map(|i: i64| (i * 7919) % 5 - 1);
+        for _ in 0..100_000 {
+            let result = jit.try_resume();
+            assert_eq!(result, cpu.try_resume());
+            assert_eq!((jit.pc(), jit.rbo()), (cpu.pc, cpu.rbo));
+            match result {
+                Ok(CpuResult::Input) => {
+                    let input = inputs.next().unwrap();
+                    assert_eq!(jit.try_input(input), cpu.try_input(input));
+                }
+                Ok(CpuResult::Output(_)) => (),
+                Ok(CpuResult::Halt) | Err(_) => break,
+            }
+        }
+        assert_eq!(jit.memory(), &cpu.memory[..]);
+    }
+
+    // Native code only wraps, anything else is left to the interpreter
+    let program = parse(include_str!("testdata/overflow.txt"));
+    for &arithmetic in &[
+        Arithmetic::Wrapping,
+        Arithmetic::Saturating,
+        Arithmetic::Checked,
+    ] {
+        let mut jit = Cpu::new(program.clone());
+        jit.arithmetic = arithmetic;
+        let mut jit = JitCpu::from(jit);
+        let mut cpu = Cpu::new(program.clone());
+        cpu.arithmetic = arithmetic;
+        for _ in 0..3 {
+            assert_eq!(jit.try_resume(), cpu.try_resume());
+        }
+    }
+
+    // A custom opcode turns the compiled output at 0 into a halt
+    let mut cpu = Cpu::new(vec![104, 1, 10, 0, 1105, 1, 0]);
+    cpu.register_opcode(10, 1, |ext| ext.set(1, 99));
+    let mut jit = JitCpu::from(cpu);
+    assert_eq!(jit.resume(), CpuResult::Output(1));
+    assert_eq!(jit.resume(), CpuResult::Halt);
+
+    // Profiles see every instruction, not just the interpreted ones
+    let program = parse(include_str!("../bin/input/day09.txt"));
+    let mut cpu = Cpu::new(program.clone());
+    cpu.start_profile();
+    let mut jit = JitCpu::from(cpu);
+    assert_eq!(jit.compute(1), Cpu::new(program.clone()).compute(1));
+    let mut cpu = Cpu::new(program);
+    cpu.start_profile();
+    cpu.compute(1);
+    let (jitted, interpreted) = (jit.into_cpu(), cpu);
+    let (jitted, interpreted) = (jitted.profile().unwrap(), interpreted.profile().unwrap());
+    assert_eq!(jitted.total, interpreted.total);
+    assert_eq!(jitted.addresses, interpreted.addresses);
+}
+
+#[cfg(test)]
+#[test]
+fn test_paged_memory() {
+    use super::memory::VEC_LIMIT;
+    use super::threaded::ThreadedCpu;
+    use super::{Cpu, ErrorKind, Memory, PagedMemory};
+
+    // Stores 7 + 8 a trillion words in, then outputs it
+    let far: i64 = 1_000_000_000_000;
+    let mut cpu = Cpu::with_memory(PagedMemory::from(vec![1101, 7, 8, far, 4, far, 99]));
+    assert_eq!(cpu.compute(0), 15);
+    assert_eq!(cpu.memory.read(far as usize), 15);
+    assert_eq!(cpu.memory.len(), far as usize + 1);
+    assert_eq!(cpu.memory.pages(), 2);
+
+    let program = super::parse(include_str!("../bin/input/day09.txt"));
+    let paged = Cpu::with_memory(PagedMemory::from(program.clone())).compute(1);
+    assert_eq!(paged, Cpu::new(program).compute(1));
+
+    // A `Vec` refuses to grow that far
+    let mut cpu = Cpu::new(vec![1101, 7, 8, far, 4, far, 99]);
+    let err = cpu.try_resume().unwrap_err();
+    assert_eq!((err.pc, err.kind), (0, ErrorKind::AddressOverflow(far)));
+    let mut threaded = ThreadedCpu::new(vec![1101, 7, 8, far, 4, far, 99]);
+    assert_eq!(threaded.try_resume(), Err(err));
+
+    // The cap starts right at `VEC_LIMIT`
+    assert_eq!(VEC_LIMIT, 1 << 24);
+    let limit = VEC_LIMIT as i64;
+    let mut cpu = Cpu::new(vec![1101, 7, 8, limit, 99]);
+    let err = cpu.try_resume().unwrap_err();
+    assert_eq!(err.kind, ErrorKind::AddressOverflow(limit));
+    assert_eq!(cpu.memory.len(), 5);
+}
+
+#[cfg(test)]
+#[test]
+fn test_hooks() {
+    use super::hooks::Hook;
+    use super::{Cpu, CpuResult};
+
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default)]
+    struct Events(Vec<String>);
+
+    impl Hook for Events {
+        fn read(&mut self, addr: usize, value: i64) {
+            self.0.push(format!("read {} {}", addr, value));
+        }
+        fn write(&mut self, addr: usize, old: i64, new: i64) {
+            self.0.push(format!("write {} {} {}", addr, old, new));
+        }
+        fn jump(&mut self, pc: usize, target: usize) {
+            self.0.push(format!("jump {} {}", pc, target));
+        }
+        fn rbo(&mut self, old: i64, new: i64) {
+            self.0.push(format!("rbo {} {}", old, new));
+        }
+        fn input(&mut self, value: i64) {
+            self.0.push(format!("input {}", value));
+        }
+        fn output(&mut self, value: i64) {
+            self.0.push(format!("output {}", value));
+        }
+        fn halt(&mut self, pc: usize) {
+            self.0.push(format!("halt {}", pc));
+        }
+    }
+
+    // in 20; arb #20; add @0, @0, @1; jnz 21, #12; hlt; out @1; hlt
+    let program = vec![3, 20, 109, 20, 22201, 0, 0, 1, 1005, 21, 12, 99, 204, 1, 99];
+    let events = Arc::new(Mutex::new(Events::default()));
+    let mut cpu = Cpu::new(program);
+    cpu.add_hook(events.clone());
+    assert_eq!(cpu.compute(5), 10);
+
+    assert_eq!(
+        events.lock().unwrap().0,
+        [
+            "input 5",
+            "write 20 0 5",
+            "rbo 0 20",
+            "read 20 5",
+            "read 20 5",
+            "write 21 0 10",
+            "read 21 10",
+            "jump 8 12",
+            "read 21 10",
+            "output 10",
+            "halt 14",
+        ]
+    );
+
+    // Custom opcodes are watched through their `Extension`
+    let events = Arc::new(Mutex::new(Events::default()));
+    let mut cpu = Cpu::new(vec![10, 5, 6, 7, 99, 3, 4, 0]);
+    cpu.register_opcode(10, 3, |ext| {
+        let max = ext.get(1)?.max(ext.get(2)?);
+        ext.set(3, max)
+    });
+    cpu.add_hook(events.clone());
+    cpu.run_collect(&[]);
+    assert_eq!(
+        events.lock().unwrap().0,
+        ["read 5 3", "read 6 4", "write 7 0 4", "halt 4"]
+    );
+
+    // Hooks don't change what the program does. Neither jump is taken, so
+    // their bad targets are never resolved.
+    for &(program, rbo) in &[
+        (&[106, 1, -5, 104, 7, 99][..], 0),
+        (&[2105, 0, 1, 104, 7, 99][..], i64::MAX),
+    ] {
+        let mut plain = Cpu::new(program.to_vec());
+        plain.rbo = rbo;
+        let events = Arc::new(Mutex::new(Events::default()));
+        let mut hooked = Cpu::new(program.to_vec());
+        hooked.rbo = rbo;
+        hooked.add_hook(events.clone());
+        assert_eq!(hooked.try_resume(), plain.try_resume());
+        assert_eq!(hooked.try_resume(), Ok(CpuResult::Halt));
+        assert_eq!(events.lock().unwrap().0, ["output 7", "halt 5"]);
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_word_types() {
+    use super::{parse_words, Cpu};
+    use num::BigInt;
+
+    let day05 = include_str!("../bin/input/day05.txt");
+    let expected = Cpu::parse(day05).compute(5);
+    let mut cpu = Cpu::with_memory(parse_words::<i32>(day05));
+    assert_eq!(i64::from(cpu.compute(5)), expected);
+    let mut cpu = Cpu::with_memory(parse_words::<BigInt>(day05));
+    assert_eq!(cpu.compute(BigInt::from(5)), BigInt::from(expected));
+
+    // Squares a word that's already past 2^63
+    let big = BigInt::from(1u64 << 63) * 3;
+    let program = format!("2,7,7,0,4,0,99,{}", big);
+    let mut cpu = Cpu::with_memory(parse_words::<BigInt>(&program));
+    assert_eq!(cpu.compute(BigInt::from(0)), &big * &big);
+    let program = "1002,7,3,0,4,0,99,12345678901234567890";
+    let mut cpu = Cpu::with_memory(parse_words::<i128>(program));
+    assert_eq!(cpu.compute(0), 12345678901234567890 * 3);
+}
+
+#[cfg(test)]
+#[test]
+fn test_arithmetic() {
+    use super::threaded::ThreadedCpu;
+    use super::{intcode_fn, Arithmetic, Cpu, CpuResult, ErrorKind};
+
+    // mul #(2^62), #4, 0; out 0; hlt
+    let program = vec![1102, 1 << 62, 4, 0, 4, 0, 99];
+    let run = |arithmetic| {
+        let mut cpu = Cpu::new(program.clone());
+        cpu.arithmetic = arithmetic;
+        cpu.try_resume()
+    };
+    assert_eq!(run(Arithmetic::Wrapping), Ok(CpuResult::Output(0)));
+    assert_eq!(run(Arithmetic::Saturating), Ok(CpuResult::Output(i64::MAX)));
+    let err = run(Arithmetic::Checked).unwrap_err();
+    assert_eq!((err.pc, err.kind), (0, ErrorKind::Overflow));
+    for &arithmetic in &[
+        Arithmetic::Wrapping,
+        Arithmetic::Saturating,
+        Arithmetic::Checked,
+    ] {
+        let mut cpu = Cpu::new(program.clone());
+        cpu.arithmetic = arithmetic;
+        assert_eq!(ThreadedCpu::from(cpu).try_resume(), run(arithmetic));
+    }
+
+    // Compiled programs always wrap
+    let mut compiled = intcode_fn!("src/intcode/testdata/overflow.txt");
+    assert_eq!(compiled.resume(), CpuResult::Output(0));
+    assert_eq!(compiled.resume(), CpuResult::Output(i64::MIN));
+    assert!(!compiled.is_interpreted());
+
+    let mut cpu = Cpu::new(vec![1101, i64::MIN, -1, 0, 99]);
+    cpu.arithmetic = Arithmetic::Checked;
+    assert_eq!(cpu.try_resume().unwrap_err().kind, ErrorKind::Overflow);
+
+    // A relative address past i64::MAX is an error whatever the policy
+    let mut cpu = Cpu::new(vec![109, i64::MAX, 204, 1, 99]);
+    assert_eq!(cpu.try_resume().unwrap_err().kind, ErrorKind::Overflow);
+    let mut cpu = Cpu::new(vec![109, 3, 204, -4, 99]);
+    assert_eq!(
+        cpu.try_resume().unwrap_err().kind,
+        ErrorKind::NegativeAddress(-1)
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn test_resume_for() {
+    use super::{Budget, Cpu, CpuResult};
+
+    // Spins forever
+    let mut cpu = Cpu::new(vec![1105, 1, 0]);
+    assert_eq!(cpu.resume_for(1000), Budget::Yield);
+    assert_eq!(cpu.pc, 0);
+    assert_eq!(cpu.resume_for(0), Budget::Yield);
+
+    // Counts [14] up to 3 in nine instructions, then outputs it. Yielding
+    // partway through doesn't change where it gets to.
+    let program = vec![1001, 14, 1, 14, 1007, 14, 3, 15, 1005, 15, 0, 4, 14, 99, 0];
+    let mut cpu = Cpu::new(program.clone());
+    let mut yields = 0;
+    let result = loop {
+        match cpu.resume_for(2) {
+            Budget::Yield => yields += 1,
+            Budget::Done(result) => break result,
+        }
+    };
+    assert_eq!(result, CpuResult::Output(3));
+    assert_eq!(yields, 4);
+    let done = Budget::Done(CpuResult::Output(3));
+    assert_eq!(Cpu::new(program.clone()).resume_for(10), done);
+
+    // Profiles count one resume however many slices it took
+    let mut cpu = Cpu::new(program);
+    cpu.start_profile();
+    while cpu.resume_for(2) == Budget::Yield {}
+    assert_eq!(cpu.resume_for(2), Budget::Done(CpuResult::Halt));
+    assert_eq!(cpu.profile().unwrap().resumes, [10, 1]);
+
+    let mut cpu = Cpu::new(vec![99]);
+    cpu.start_profile();
+    assert_eq!(cpu.resume_for(1), Budget::Done(CpuResult::Halt));
+}
+
+#[cfg(test)]
+#[test]
+fn test_loop_detection() {
+    use super::{Budget, Cpu, CpuResult, ErrorKind};
+
+    // Bumps [20] once, then flips [21] between 0 and 1 forever
+    let program = vec![
+        1001, 20, 1, 20, // 0: add [20], #1, 20
+        1007, 21, 1, 21, // 4: lt [21], #1, 21
+        1105, 1, 4, // 8: jnz #1, #4
+    ];
+    let found = ErrorKind::InfiniteLoop {
+        start: 4,
+        length: 4,
+    };
+    let mut cpu = Cpu::new(program.clone());
+    cpu.start_loop_detection(10);
+    assert_eq!(cpu.try_resume().unwrap_err().kind, found);
+    // Carrying on finds it again
+    assert_eq!(cpu.try_resume().unwrap_err().kind, found);
+
+    // Measuring the loop doesn't run past the budget
+    let mut cpu = Cpu::new(program.clone());
+    cpu.start_loop_detection(10);
+    let mut yields = 0;
+    let err = loop {
+        match cpu.try_resume_for(3) {
+            Ok(Budget::Yield) => yields += 1,
+            Ok(Budget::Done(result)) => panic!("unexpected {:?}", result),
+            Err(err) => break err,
+        }
+    };
+    assert_eq!(err.kind, found);
+    assert!(yields > 0);
+
+    let mut cpu = Cpu::new(program);
+    cpu.start_loop_detection(7);
+    assert_eq!(cpu.try_run(()).unwrap_err().kind, found);
+
+    // Outputs the next number forever, which never repeats a state
+    let mut cpu = Cpu::new(vec![1001, 9, 1, 9, 4, 9, 1105, 1, 0, 0]);
+    cpu.start_loop_detection(1);
+    for i in 1..100 {
+        assert_eq!(cpu.resume(), CpuResult::Output(i));
+    }
+    // Counts to 100000 before looping, more states than are kept at once
+    let program = vec![
+        1001, 20, 1, 20, // 0: add [20], #1, 20
+        1007, 20, 100000, 21, // 4: lt [20], #100000, 21
+        1005, 21, 0, // 8: jnz [21], #0
+        1105, 1, 11, // 11: jnz #1, #11
+    ];
+    let mut cpu = Cpu::new(program);
+    cpu.start_loop_detection(1);
+    let err = cpu.try_resume().unwrap_err();
+    assert_eq!(
+        err.kind,
+        ErrorKind::InfiniteLoop {
+            start: 11,
+            length: 1
+        }
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn test_custom_opcodes() {
+    use super::{Cpu, CpuResult, ErrorKind, Instruction};
+
+    let program = vec![
+        1110, 5, 7, 20, // 0: max #5, #7, 20
+        4, 20, // 4: out [20]
+        1111, -1, 11, // 6: jneg #-1, #11
+        4, 20, // 9: out [20]
+        99, // 11: hlt
+    ];
+    let mut cpu = Cpu::new(program.clone());
+    cpu.register_opcode(10, 3, |ext| {
+        let max = ext.get(1)?.max(ext.get(2)?);
+        ext.set(3, max)
+    });
+    cpu.register_opcode(11, 2, |ext| {
+        if ext.get(1)? < 0 {
+            ext.jump(ext.get(2)?)?;
+        }
+        Ok(())
+    });
+
+    let step = cpu.step().unwrap();
+    assert_eq!(
+        step.instruction,
+        Instruction::Custom {
+            opcode: 10,
+            arity: 3
+        }
+    );
+    assert_eq!(step.instruction.to_string(), "op10");
+    assert_eq!(cpu.pc, 4);
+    assert_eq!(cpu.resume(), CpuResult::Output(7));
+    assert_eq!(cpu.resume(), CpuResult::Halt);
+    assert_eq!(cpu.pc, 11);
+
+    cpu.unregister_opcode(11);
+    cpu.pc = 6;
+    let err = cpu.try_resume().unwrap_err();
+    assert_eq!(err.kind, ErrorKind::UnknownOpcode(11));
+
+    // Stores in mode 3 are relative, like the built in ones
+    let mut cpu = Cpu::new(vec![31110, 2, 3, 1, 99]);
+    cpu.register_opcode(10, 3, |ext| {
+        let max = ext.get(1)?.max(ext.get(2)?);
+        ext.set(3, max)
+    });
+    cpu.rbo = 5;
+    assert_eq!(cpu.resume(), CpuResult::Halt);
+    assert_eq!(cpu.memory[6], 3);
+
+    let mut cpu = Cpu::new(vec![310, 0, 0, 0]);
+    cpu.register_opcode(10, 1, |ext| ext.get(1).map(drop));
+    let err = cpu.try_resume().unwrap_err();
+    assert_eq!(err.kind, ErrorKind::UnknownMode { arg: 1, mode: 3 });
+}
+
+#[cfg(test)]
+#[test]
+fn test_cpu_is_send() {
+    use super::{Cpu, PagedMemory};
+    use num::BigInt;
+
+    // Tracers, hooks and custom opcodes are all boxed, so this would break
+    // quietly if one of them stopped requiring `Send`
+    fn assert_send<T: Send>() {}
+    assert_send::<Cpu>();
+    assert_send::<Cpu<BigInt, PagedMemory<BigInt>>>();
+}
+
+#[cfg(test)]
+#[test]
+fn test_run_async() {
+    use super::asyncio::channel;
+    use super::executor::{block_on, Executor};
+    use super::{AsyncChannelIO, Cpu, Exit};
+
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    // Amplifier feedback loop from day 7
+    let program = parse(
+        "3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5",
+    );
+    let (senders, receivers): (Vec<_>, Vec<_>) = (0..5).map(|_| channel()).unzip();
+    for (sender, phase) in senders.iter().zip(&[9, 8, 7, 6, 5]) {
+        sender.send(*phase);
+    }
+    senders[0].send(0);
+
+    let mut executor = Executor::new();
+    let thrust = Rc::new(Cell::new(0));
+    for (i, input) in receivers.into_iter().enumerate() {
+        let mut cpu = Cpu::new(program.clone());
+        let mut io = AsyncChannelIO::new(input, senders[(i + 1) % 5].clone());
+        let thrust = thrust.clone();
+        executor.spawn(async move {
+            assert_eq!(cpu.run_async(&mut io).await, Exit::Halt);
+            thrust.set(io.last_output);
+        });
+    }
+    executor.run();
+    assert_eq!(executor.pending(), 0);
+    assert_eq!(thrust.get(), 139_629_729);
+
+    // Lots of cpus, each adding one to what it's given
+    let (first, mut input) = channel();
+    for _ in 0..500 {
+        let (output, next) = channel();
+        let mut cpu = Cpu::new(vec![3, 0, 1001, 0, 1, 0, 4, 0, 99]);
+        let io = AsyncChannelIO::new(std::mem::replace(&mut input, next), output);
+        executor.spawn(async move {
+            assert_eq!(cpu.run_async(io).await, Exit::Halt);
+        });
+    }
+    first.send(0);
+    executor.run();
+    assert_eq!(block_on(input.recv()), Some(500));
+
+    // Waiting on input that never comes leaves the task pending
+    let (_sender, input) = channel();
+    let (output, _) = channel();
+    let mut cpu = Cpu::new(vec![3, 0, 99]);
+    executor.spawn(async move {
+        let _ = cpu.run_async(AsyncChannelIO::new(input, output)).await;
+    });
+    executor.run();
+    assert_eq!(executor.pending(), 1);
+
+    // Unless nothing can send it any more
+    let (sender, input) = channel();
+    let (output, _) = channel();
+    drop(sender);
+    let mut cpu = Cpu::new(vec![3, 0, 99]);
+    let exit = block_on(cpu.run_async(AsyncChannelIO::new(input, output)));
+    assert_eq!(exit, Exit::Stop);
+}
+
+#[cfg(test)]
+#[test]
+fn test_network() {
+    use super::asm::assemble;
+    use super::network::{Network, NetworkHook, Packet, ROUND_PACKETS};
+
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // 0 sends a packet to 1, then everyone passes what they get on to the
+    // NAT with y bumped
+    let program = assemble(
+        "
+                in addr
+                jnz addr, #recv
+                out #1
+                out #10
+                out #20
+        recv:   in x
+                eq x, #-1, empty
+                jnz empty, #recv
+                in y
+                add y, #1, y
+                out #255
+                out x
+                out y
+                jmp #recv
+        addr:   .data 0
+        x:      .data 0
+        y:      .data 0
+        empty:  .data 0
+        ",
+    )
+    .unwrap();
+
+    #[derive(Default)]
+    struct Traffic(Vec<Packet>);
+
+    impl NetworkHook for Traffic {
+        fn packet(&mut self, packet: &Packet) {
+            self.0.push(*packet);
+        }
+    }
+
+    let traffic = Rc::new(RefCell::new(Traffic::default()));
+    let mut network = Network::new(&program, 3).with_nat(255);
+    network.add_hook(traffic.clone());
+    let packet = |src, dest, y| Packet {
+        src,
+        dest,
+        x: 10,
+        y,
+    };
+
+    assert_eq!(network.round().unwrap(), None);
+    assert_eq!(
+        traffic.borrow().0,
+        vec![packet(0, 1, 20), packet(1, 255, 21)]
+    );
+    assert!(!network.idle());
+    assert_eq!(network.round().unwrap(), Some(packet(255, 0, 21)));
+    assert!(network.idle());
+    assert_eq!(network.round().unwrap(), None);
+    assert_eq!(network.nat_packet(), Some(packet(0, 255, 22)));
+    assert_eq!(network.round().unwrap(), Some(packet(255, 0, 22)));
+    assert_eq!(traffic.borrow().0.len(), 3);
+
+    // Without a NAT nothing wakes it up again
+    let mut network = Network::new(&program, 3);
+    network.run().unwrap();
+    assert!(network.idle());
+    assert!(!network.halted());
+
+    // Machines that send forever without reading take turns
+    let flood = assemble("loop: out #1\n out #2\n out #3\n jmp #loop").unwrap();
+    let traffic = Rc::new(RefCell::new(Traffic::default()));
+    let mut network = Network::new(&flood, 2);
+    network.add_hook(traffic.clone());
+    assert_eq!(network.round().unwrap(), None);
+    let senders: Vec<_> = traffic.borrow().0.iter().map(|p| p.src).collect();
+    assert_eq!(senders.len(), 2 * ROUND_PACKETS);
+    assert_eq!(senders[ROUND_PACKETS - 1..ROUND_PACKETS + 1], [0, 1]);
+}
+
+#[cfg(test)]
+#[test]
+fn test_circuit() {
+    use super::circuit::{Circuit, CircuitError};
+
+    let echo = vec![3, 5, 4, 5, 99, 0];
+    let add = vec![3, 11, 3, 12, 1, 11, 12, 11, 4, 11, 99, 0, 0];
+
+    // Fans out to two echoes, then back in to an adder
+    let mut circuit = Circuit::new();
+    let source = circuit.add_node(&echo, &[5]);
+    let left = circuit.add_node(&echo, &[]);
+    let right = circuit.add_node(&echo, &[]);
+    let sum = circuit.add_node(&add, &[]);
+    circuit.connect(source, left);
+    circuit.connect(source, right);
+    circuit.connect(left, sum);
+    circuit.connect(right, sum);
+    circuit.run().unwrap();
+    assert_eq!(circuit.outputs(source), &[5]);
+    assert_eq!(circuit.outputs(sum), &[10]);
+
+    // Day 7's example feedback loop
+    let program = parse(
+        "3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5",
+    );
+    let mut circuit = Circuit::new();
+    for (i, &phase) in [9, 8, 7, 6, 5].iter().enumerate() {
+        let inputs = if i == 0 { vec![phase, 0] } else { vec![phase] };
+        circuit.add_node(&program, &inputs);
+    }
+    for i in 0..5 {
+        circuit.connect(i, (i + 1) % 5);
+    }
+    circuit.run().unwrap();
+    assert_eq!(circuit.outputs(4).last(), Some(&139_629_729));
+
+    let mut circuit = Circuit::new();
+    circuit.add_node(&echo, &[1]);
+    circuit.add_node(&add, &[1]);
+    let err = circuit.run().unwrap_err();
+    assert_eq!(err, CircuitError::Deadlock { waiting: vec![1] });
+}
+
+#[cfg(test)]
+#[test]
+fn test_ascii_io() {
+    use super::asm::assemble;
+    use super::{AsciiIO, Cpu, Exit};
+
+    // Echoes a line back in upper case, then outputs its length
+    let program = assemble(
+        r#"
+                arb #stack
+                out #62
+        read:   in char
+                eq char, #10, done
+                jnz done, #end
+                lt char, #97, lower
+                jnz lower, #echo
+                add char, #-32, char
+        echo:   out char
+                add len, #1, len
+                jmp #read
+        end:    out #10
+                out #62
+                add len, #1000, len
+                out len
+                hlt
+        char:   .data 0
+        done:   .data 0
+        lower:  .data 0
+        len:    .data 0
+        stack:
+        "#,
+    )
+    .unwrap();
+
+    let mut io = AsciiIO::new();
+    io.push_line("Hi there");
+    assert!(io.has_input());
+    assert_eq!(Cpu::new(program).run(&mut io), Exit::Halt);
+    assert!(!io.has_input());
+    assert_eq!(io.lines, vec![">HI THERE"]);
+    assert_eq!(io.partial(), ">");
+    assert_eq!(io.values, vec![1008]);
+    assert_eq!(io.take_text(), ">HI THERE\n>");
+    assert!(io.lines.is_empty());
+}
+
+#[cfg(test)]
+#[test]
+fn test_outputs() {
+    use super::asm::assemble;
+    use super::{Cpu, ErrorKind};
+
+    // Outputs the sum of each pair of inputs until a zero
+    let program = assemble(
+        "
+        loop:   in a
+                jz a, #end
+                in b
+                add a, b, a
+                out a
+                jmp #loop
+        end:    hlt
+        a:      .data 0
+        b:      .data 0
+        ",
+    )
+    .unwrap();
+
+    let mut cpu = Cpu::new(program.clone());
+    let sums: Result<Vec<_>, _> = cpu.outputs(vec![1, 2, 3, 4, 0]).collect();
+    assert_eq!(sums, Ok(vec![3, 7]));
+
+    // Inputs are only taken when asked for
+    let mut cpu = Cpu::new(program.clone());
+    let mut taken = 0;
+    let inputs = (1..).inspect(|_| taken += 1);
+    let sums: Vec<_> = cpu.outputs(inputs).take(3).map(Result::unwrap).collect();
+    assert_eq!(sums, vec![3, 7, 11]);
+    assert_eq!(taken, 6);
+
+    assert_eq!(Cpu::new(program.clone()).run_collect(&[5, 5, 0]), vec![10]);
+
+    let mut cpu = Cpu::new(program.clone());
+    let mut outputs = cpu.outputs(vec![1, 2, 3]);
+    assert_eq!(outputs.next(), Some(Ok(3)));
+    let err = outputs.next().unwrap().unwrap_err();
+    assert_eq!(err.kind, ErrorKind::OutOfInput);
+    assert_eq!(outputs.next(), None);
+
+    let err = Cpu::new(program).try_run_collect(&[1]).unwrap_err();
+    assert_eq!((err.pc, err.kind), (5, ErrorKind::OutOfInput));
+}
+
+#[cfg(test)]
+#[test]
+fn test_io_flow() {
+    use super::{AsciiIO, ChannelIO, Cpu, Exit, Flow, Input, IO};
+
+    use std::sync::mpsc::channel;
+
+    // Counts up from 1 forever
+    let counter = vec![1001, 9, 1, 9, 4, 9, 1105, 1, 0, 0];
+
+    struct Take(Vec<i64>, usize);
+
+    impl IO for Take {
+        fn input(&mut self) -> Input {
+            Input::Stop
+        }
+        fn output(&mut self, value: i64) -> Flow {
+            self.0.push(value);
+            match self.0.len() % self.1 {
+                0 => Flow::Pause,
+                _ => Flow::Continue,
+            }
+        }
+    }
+
+    let mut cpu = Cpu::new(counter);
+    let mut io = Take(Vec::new(), 3);
+    assert_eq!(cpu.run(&mut io), Exit::Pause);
+    assert_eq!(io.0, vec![1, 2, 3]);
+    // Carries on after the output that paused it
+    assert_eq!(cpu.run(&mut io), Exit::Pause);
+    assert_eq!(io.0, vec![1, 2, 3, 4, 5, 6]);
+
+    assert_eq!(Cpu::new(vec![3, 0, 99]).run(()), Exit::Stop);
+    assert_eq!(Cpu::new(vec![4, 0, 99]).run(()), Exit::Halt);
+
+    // Input that isn't there yet leaves the cpu waiting for it
+    let mut cpu = Cpu::new(vec![3, 9, 4, 9, 3, 9, 4, 9, 99, 0]);
+    let mut io = AsciiIO::new();
+    assert_eq!(cpu.run(&mut io), Exit::Pause);
+    assert_eq!(cpu.pc, 0);
+    io.push_line("a");
+    assert_eq!(cpu.run(&mut io), Exit::Halt);
+    assert_eq!(io.lines, vec!["a"]);
+
+    let (input, receiver) = channel();
+    let (sender, output) = channel();
+    input.send(5).unwrap();
+    drop(input);
+    let mut cpu = Cpu::new(vec![3, 9, 4, 9, 3, 9, 4, 9, 99, 0]);
+    assert_eq!(cpu.run(ChannelIO::new(receiver, sender)), Exit::Stop);
+    assert_eq!(output.recv(), Ok(5));
+}
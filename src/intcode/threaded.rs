@@ -0,0 +1,259 @@
+//! An alternative to `Cpu::resume` which decodes each instruction's opcode and
+//! addressing modes once and caches them in a compact table, instead of doing
+//! the divisions to pick them apart every time it runs. Operands are still
+//! read from memory and writes to an opcode throw away its cached entry, so
+//! self-modifying programs behave exactly as they do on `Cpu`.
+
+use super::cpu::{decode, unwrap};
+use super::io::run_with;
+use super::memory::VEC_LIMIT;
+use super::{Arithmetic, Cpu, CpuError, CpuResult, ErrorKind, Exit, Operand, SingleIO, Word, IO};
+
+pub struct ThreadedCpu {
+    memory: Vec<i64>,
+    pub pc: usize,
+    pub rbo: i64,
+    pub arithmetic: Arithmetic,
+    /// Opcode and addressing modes of the instruction at each address,
+    /// packed as `opcode | modes << 8` with two bits per mode. Zero if the
+    /// address hasn't been decoded since it was last written.
+    ops: Vec<u16>,
+}
+
+/// Only memory, registers and the arithmetic policy carry over. Tracing,
+/// profiling, hooks and loop detection are dropped, and `into_cpu` doesn't
+/// give them back. Custom opcodes are dropped too, so running one is an
+/// `ErrorKind::UnknownOpcode` like any other opcode `Cpu` doesn't know.
+impl From<Cpu> for ThreadedCpu {
+    fn from(cpu: Cpu) -> Self {
+        ThreadedCpu {
+            ops: vec![0; cpu.memory.len()],
+            memory: cpu.memory,
+            pc: cpu.pc,
+            rbo: cpu.rbo,
+            arithmetic: cpu.arithmetic,
+        }
+    }
+}
+
+impl ThreadedCpu {
+    pub fn new(memory: Vec<i64>) -> Self {
+        Cpu::new(memory).into()
+    }
+
+    pub fn into_cpu(self) -> Cpu {
+        let mut cpu = Cpu::new(self.memory);
+        cpu.pc = self.pc;
+        cpu.rbo = self.rbo;
+        cpu.arithmetic = self.arithmetic;
+        cpu
+    }
+
+    pub fn memory(&self) -> &[i64] {
+        &self.memory
+    }
+
+    /// Mutable access to memory. This throws away every decoded instruction.
+    pub fn memory_mut(&mut self) -> &mut Vec<i64> {
+        for op in &mut self.ops {
+            *op = 0;
+        }
+        &mut self.memory
+    }
+
+    pub fn run(&mut self, io: impl IO) -> Exit {
+        unwrap(self.try_run(io))
+    }
+
+    pub fn try_run(&mut self, io: impl IO) -> Result<Exit, CpuError> {
+        run_with(self, io, Self::try_resume, Self::try_input)
+    }
+
+    pub fn compute(&mut self, input: i64) -> i64 {
+        unwrap(self.try_compute(input))
+    }
+
+    pub fn try_compute(&mut self, input: i64) -> Result<i64, CpuError> {
+        let mut io = SingleIO::new(input);
+        let _ = self.try_run(&mut io)?;
+        Ok(io.output)
+    }
+
+    pub fn resume(&mut self) -> CpuResult {
+        unwrap(self.try_resume())
+    }
+
+    pub fn try_resume(&mut self) -> Result<CpuResult, CpuError> {
+        loop {
+            let op = self.fetch()?;
+            match op & 0xff {
+                1 => {
+                    let (a, b) = (self.load(op, 1)?, self.load(op, 2)?);
+                    let value = self.overflow(a.add_with(&b, self.arithmetic))?;
+                    self.store(op, 3, value)?;
+                    self.pc += 4;
+                }
+                2 => {
+                    let (a, b) = (self.load(op, 1)?, self.load(op, 2)?);
+                    let value = self.overflow(a.mul_with(&b, self.arithmetic))?;
+                    self.store(op, 3, value)?;
+                    self.pc += 4;
+                }
+                3 => break Ok(CpuResult::Input),
+                4 => {
+                    let value = self.load(op, 1)?;
+                    self.pc += 2;
+                    break Ok(CpuResult::Output(value));
+                }
+                5 => {
+                    if self.load(op, 1)? != 0 {
+                        self.pc = self.address(self.load(op, 2)?)?;
+                    } else {
+                        self.pc += 3;
+                    }
+                }
+                6 => {
+                    if self.load(op, 1)? == 0 {
+                        self.pc = self.address(self.load(op, 2)?)?;
+                    } else {
+                        self.pc += 3;
+                    }
+                }
+                7 => {
+                    let value = (self.load(op, 1)? < self.load(op, 2)?) as i64;
+                    self.store(op, 3, value)?;
+                    self.pc += 4;
+                }
+                8 => {
+                    let value = (self.load(op, 1)? == self.load(op, 2)?) as i64;
+                    self.store(op, 3, value)?;
+                    self.pc += 4;
+                }
+                9 => {
+                    let rbo = self.rbo.add_with(&self.load(op, 1)?, self.arithmetic);
+                    self.rbo = self.overflow(rbo)?;
+                    self.pc += 2;
+                }
+                99 => break Ok(CpuResult::Halt),
+                _ => unreachable!("only valid instructions are cached"),
+            }
+        }
+    }
+
+    pub fn input(&mut self, input: i64) {
+        unwrap(self.try_input(input))
+    }
+
+    pub fn try_input(&mut self, input: i64) -> Result<(), CpuError> {
+        let op = self.fetch()?;
+        if op & 0xff != 3 {
+            return Err(self.error(ErrorKind::UnexpectedInput));
+        }
+        self.store(op, 1, input)?;
+        self.pc += 2;
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn fetch(&mut self) -> Result<u16, CpuError> {
+        match self.ops.get(self.pc) {
+            Some(&op) if op != 0 => Ok(op),
+            _ => self.decode(),
+        }
+    }
+
+    #[cold]
+    fn decode(&mut self) -> Result<u16, CpuError> {
+        let pc = self.pc;
+        // Checks the instruction is valid, so errors match `Cpu`'s
+        let instruction = decode(&self.memory, pc)?;
+        let opcode = instruction.opcode();
+        let dest = instruction.destination().map(|_| instruction.size() - 2);
+
+        let mut modes = 0;
+        let mut digits = self.read(pc) / 100;
+        for arg in 0..instruction.size() - 1 {
+            let mut mode = digits % 10;
+            // Stores treat immediate mode as position and 3 as relative, like `decode`
+            if dest == Some(arg) {
+                mode &= !1;
+            }
+            modes |= mode << (2 * arg);
+            digits /= 10;
+        }
+        let op = (opcode | modes << 8) as u16;
+
+        if pc >= self.ops.len() {
+            self.ops.resize(pc + 1, 0);
+        }
+        self.ops[pc] = op;
+        Ok(op)
+    }
+
+    #[inline(always)]
+    fn operand(&self, op: u16, arg: usize) -> Operand {
+        let value = self.read(self.pc + arg);
+        match (op >> (2 * arg + 6)) & 3 {
+            0 => Operand::Position(value),
+            1 => Operand::Immediate(value),
+            _ => Operand::Relative(value),
+        }
+    }
+
+    #[inline(always)]
+    fn load(&self, op: u16, arg: usize) -> Result<i64, CpuError> {
+        let addr = match self.operand(op, arg) {
+            Operand::Immediate(value) => return Ok(value),
+            Operand::Position(addr) => addr,
+            Operand::Relative(offset) => self.overflow(self.rbo.checked_add(offset))?,
+        };
+        Ok(self.read(self.address(addr)?))
+    }
+
+    #[inline(always)]
+    fn store(&mut self, op: u16, arg: usize, value: i64) -> Result<(), CpuError> {
+        let addr = match self.operand(op, arg) {
+            Operand::Position(addr) => addr,
+            Operand::Relative(offset) => self.overflow(self.rbo.checked_add(offset))?,
+            Operand::Immediate(_) => unreachable!("destinations never decode as immediates"),
+        };
+        let addr = self.address(addr)?;
+        if addr >= VEC_LIMIT {
+            return Err(self.error(ErrorKind::AddressOverflow(addr as i64)));
+        }
+        if addr >= self.memory.len() {
+            self.memory.resize(addr + 1, 0);
+        }
+        self.memory[addr] = value;
+        // Writing to an opcode means it has to be decoded again
+        if let Some(op) = self.ops.get_mut(addr) {
+            *op = 0;
+        }
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn address(&self, addr: i64) -> Result<usize, CpuError> {
+        if addr < 0 {
+            return Err(self.error(ErrorKind::NegativeAddress(addr)));
+        }
+        Ok(addr as usize)
+    }
+
+    #[inline(always)]
+    fn overflow(&self, result: Option<i64>) -> Result<i64, CpuError> {
+        result.ok_or_else(|| self.error(ErrorKind::Overflow))
+    }
+
+    fn read(&self, addr: usize) -> i64 {
+        self.memory.get(addr).copied().unwrap_or(0)
+    }
+
+    fn error(&self, kind: ErrorKind) -> CpuError {
+        CpuError {
+            pc: self.pc,
+            instruction: self.read(self.pc),
+            kind,
+        }
+    }
+}
@@ -0,0 +1,301 @@
+//! Execution traces, recorded by attaching a `Tracer` to a `Cpu`.
+//!
+//! The JSON format has one object per executed instruction:
+//!
+//! ```text
+//! {"n":0,"pc":0,"op":1,"args":[[5,7],[null,2],[5,7]],"write":[5,9]}
+//! ```
+//!
+//! `args` holds the resolved `[address, value]` of each operand (address is
+//! null for immediates, and the value of a destination is read before the
+//! write). A jump that isn't taken only lists its condition. `write`, `rbo`,
+//! `in` and `out` are only present when the instruction wrote memory, moved
+//! the relative base or did I/O. `write` holds an address and value for each
+//! write, only custom opcodes make more than one.
+//!
+//! The binary format starts with `ICTR` and a version byte, followed by one
+//! record per instruction made of a flags byte and LEB128 varints. It can be
+//! read back with `read_binary`.
+
+use smallvec::SmallVec;
+
+use std::io::{self, Read, Write};
+
+const MAGIC: &[u8; 4] = b"ICTR";
+const VERSION: u8 = 2;
+
+const FLAG_WRITE: u8 = 1;
+const FLAG_RBO: u8 = 2;
+const FLAG_INPUT: u8 = 4;
+const FLAG_OUTPUT: u8 = 8;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceRecord {
+    /// Index of the instruction since tracing started
+    pub n: u64,
+    pub pc: usize,
+    pub opcode: i64,
+    pub args: SmallVec<[Access; 3]>,
+    /// Address and new value of each word written, in order
+    pub writes: SmallVec<[(usize, i64); 1]>,
+    /// New relative base, if the instruction changed it
+    pub rbo: Option<i64>,
+    pub io: Option<IoEvent>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Access {
+    /// `None` for immediate operands
+    pub addr: Option<usize>,
+    pub value: i64,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum IoEvent {
+    Input(i64),
+    Output(i64),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TraceFormat {
+    Json,
+    Binary,
+}
+
+pub struct Tracer {
+    format: TraceFormat,
+    out: Box<dyn Write + Send>,
+    count: u64,
+    error: Option<io::Error>,
+}
+
+impl Tracer {
+    pub fn new(format: TraceFormat, out: impl Write + Send + 'static) -> Self {
+        let mut tracer = Tracer {
+            format,
+            out: Box::new(out),
+            count: 0,
+            error: None,
+        };
+        if format == TraceFormat::Binary {
+            let header = tracer
+                .out
+                .write_all(MAGIC)
+                .and_then(|_| tracer.out.write_all(&[VERSION]));
+            tracer.error = header.err();
+        }
+        tracer
+    }
+
+    /// Number of instructions recorded so far
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Records the next instruction. The record's `n` is filled in here.
+    pub fn record(&mut self, mut record: TraceRecord) {
+        record.n = self.count;
+        self.count += 1;
+
+        // Once the output fails there's no point trying to keep going, the
+        // error is reported by `finish`
+        if self.error.is_some() {
+            return;
+        }
+        let result = match self.format {
+            TraceFormat::Json => write_json(&mut self.out, &record),
+            TraceFormat::Binary => write_binary(&mut self.out, &record),
+        };
+        self.error = result.err();
+    }
+
+    /// Flushes the trace, reporting the first error that happened while writing it
+    pub fn finish(mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(err) => Err(err),
+            None => self.out.flush(),
+        }
+    }
+}
+
+fn write_json(out: &mut impl Write, record: &TraceRecord) -> io::Result<()> {
+    write!(
+        out,
+        r#"{{"n":{},"pc":{},"op":{},"args":["#,
+        record.n, record.pc, record.opcode
+    )?;
+    for (i, arg) in record.args.iter().enumerate() {
+        if i > 0 {
+            out.write_all(b",")?;
+        }
+        match arg.addr {
+            Some(addr) => write!(out, "[{},{}]", addr, arg.value)?,
+            None => write!(out, "[null,{}]", arg.value)?,
+        }
+    }
+    out.write_all(b"]")?;
+    if !record.writes.is_empty() {
+        out.write_all(br#","write":["#)?;
+        for (i, (addr, value)) in record.writes.iter().enumerate() {
+            if i > 0 {
+                out.write_all(b",")?;
+            }
+            write!(out, "{},{}", addr, value)?;
+        }
+        out.write_all(b"]")?;
+    }
+    if let Some(rbo) = record.rbo {
+        write!(out, r#","rbo":{}"#, rbo)?;
+    }
+    match record.io {
+        Some(IoEvent::Input(value)) => write!(out, r#","in":{}"#, value)?,
+        Some(IoEvent::Output(value)) => write!(out, r#","out":{}"#, value)?,
+        None => (),
+    }
+    out.write_all(b"}\n")
+}
+
+fn write_binary(out: &mut impl Write, record: &TraceRecord) -> io::Result<()> {
+    let mut flags = 0;
+    if !record.writes.is_empty() {
+        flags |= FLAG_WRITE;
+    }
+    if record.rbo.is_some() {
+        flags |= FLAG_RBO;
+    }
+    match record.io {
+        Some(IoEvent::Input(_)) => flags |= FLAG_INPUT,
+        Some(IoEvent::Output(_)) => flags |= FLAG_OUTPUT,
+        None => (),
+    }
+
+    let mut buf = SmallVec::<[u8; 64]>::new();
+    buf.push(flags);
+    write_uvarint(&mut buf, record.pc as u64);
+    write_ivarint(&mut buf, record.opcode);
+    buf.push(record.args.len() as u8);
+    for arg in &record.args {
+        match arg.addr {
+            // Addresses are offset by one so that zero can mean immediate
+            Some(addr) => write_uvarint(&mut buf, addr as u64 + 1),
+            None => write_uvarint(&mut buf, 0),
+        }
+        write_ivarint(&mut buf, arg.value);
+    }
+    if !record.writes.is_empty() {
+        write_uvarint(&mut buf, record.writes.len() as u64);
+        for &(addr, value) in &record.writes {
+            write_uvarint(&mut buf, addr as u64);
+            write_ivarint(&mut buf, value);
+        }
+    }
+    if let Some(rbo) = record.rbo {
+        write_ivarint(&mut buf, rbo);
+    }
+    if let Some(IoEvent::Input(value)) | Some(IoEvent::Output(value)) = record.io {
+        write_ivarint(&mut buf, value);
+    }
+    out.write_all(&buf)
+}
+
+/// Reads back a trace written in `TraceFormat::Binary`
+pub fn read_binary(mut input: impl Read) -> io::Result<Vec<TraceRecord>> {
+    let mut header = [0; 5];
+    input.read_exact(&mut header)?;
+    if &header[..4] != MAGIC || header[4] != VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a version 2 binary trace",
+        ));
+    }
+
+    let mut records = Vec::new();
+    loop {
+        let mut flags = [0];
+        if input.read(&mut flags)? == 0 {
+            break Ok(records);
+        }
+        let flags = flags[0];
+
+        let pc = read_uvarint(&mut input)? as usize;
+        let opcode = read_ivarint(&mut input)?;
+        let mut nargs = [0];
+        input.read_exact(&mut nargs)?;
+        let mut args = SmallVec::new();
+        for _ in 0..nargs[0] {
+            let addr = match read_uvarint(&mut input)? {
+                0 => None,
+                addr => Some(addr as usize - 1),
+            };
+            let value = read_ivarint(&mut input)?;
+            args.push(Access { addr, value });
+        }
+
+        let mut writes = SmallVec::new();
+        if flags & FLAG_WRITE != 0 {
+            for _ in 0..read_uvarint(&mut input)? {
+                let addr = read_uvarint(&mut input)? as usize;
+                writes.push((addr, read_ivarint(&mut input)?));
+            }
+        }
+        let rbo = if flags & FLAG_RBO != 0 {
+            Some(read_ivarint(&mut input)?)
+        } else {
+            None
+        };
+        let io = if flags & FLAG_INPUT != 0 {
+            Some(IoEvent::Input(read_ivarint(&mut input)?))
+        } else if flags & FLAG_OUTPUT != 0 {
+            Some(IoEvent::Output(read_ivarint(&mut input)?))
+        } else {
+            None
+        };
+
+        records.push(TraceRecord {
+            n: records.len() as u64,
+            pc,
+            opcode,
+            args,
+            writes,
+            rbo,
+            io,
+        });
+    }
+}
+
+fn write_uvarint(buf: &mut SmallVec<[u8; 64]>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            buf.push(byte);
+            break;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+fn write_ivarint(buf: &mut SmallVec<[u8; 64]>, value: i64) {
+    write_uvarint(buf, ((value << 1) ^ (value >> 63)) as u64);
+}
+
+fn read_uvarint(input: &mut impl Read) -> io::Result<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0];
+        input.read_exact(&mut byte)?;
+        value |= ((byte[0] & 0x7f) as u64) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "varint too long",
+    ))
+}
+
+fn read_ivarint(input: &mut impl Read) -> io::Result<i64> {
+    let value = read_uvarint(input)?;
+    Ok((value >> 1) as i64 ^ -((value & 1) as i64))
+}
@@ -0,0 +1,80 @@
+//! Word types a `Cpu` can run with. `i64` is the default, `i32` is enough
+//! for small programs and `i128` or `BigInt` for ones that overflow.
+
+use num::{BigInt, FromPrimitive, Integer, ToPrimitive};
+
+use std::fmt::{Debug, Display};
+use std::hash::Hash;
+use std::str::FromStr;
+
+/// What `add` and `mul` do when the result doesn't fit in a word
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Arithmetic {
+    #[default]
+    Wrapping,
+    /// Stops with `ErrorKind::Overflow`
+    Checked,
+    Saturating,
+}
+
+pub trait Word:
+    Integer + ToPrimitive + FromPrimitive + FromStr + Clone + Debug + Display + Hash + 'static
+{
+    /// `self + other`, or `None` if it overflows under `Arithmetic::Checked`
+    fn add_with(&self, other: &Self, arithmetic: Arithmetic) -> Option<Self>;
+
+    /// `self * other`, or `None` if it overflows under `Arithmetic::Checked`
+    fn mul_with(&self, other: &Self, arithmetic: Arithmetic) -> Option<Self>;
+}
+
+macro_rules! primitive_word {
+    ($($ty:ty),*) => {
+        $(
+            impl Word for $ty {
+                #[inline(always)]
+                fn add_with(&self, other: &Self, arithmetic: Arithmetic) -> Option<Self> {
+                    match arithmetic {
+                        Arithmetic::Wrapping => Some(self.wrapping_add(*other)),
+                        Arithmetic::Checked => self.checked_add(*other),
+                        Arithmetic::Saturating => Some(self.saturating_add(*other)),
+                    }
+                }
+
+                #[inline(always)]
+                fn mul_with(&self, other: &Self, arithmetic: Arithmetic) -> Option<Self> {
+                    match arithmetic {
+                        Arithmetic::Wrapping => Some(self.wrapping_mul(*other)),
+                        Arithmetic::Checked => self.checked_mul(*other),
+                        Arithmetic::Saturating => Some(self.saturating_mul(*other)),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+primitive_word!(i32, i64, i128);
+
+/// Never overflows, so every policy is the same
+impl Word for BigInt {
+    fn add_with(&self, other: &Self, _: Arithmetic) -> Option<Self> {
+        Some(self + other)
+    }
+
+    fn mul_with(&self, other: &Self, _: Arithmetic) -> Option<Self> {
+        Some(self * other)
+    }
+}
+
+/// Parses a comma separated program, panicking on anything that isn't a word
+pub fn parse_words<W: Word>(input: &str) -> Vec<W> {
+    input
+        .trim()
+        .split(',')
+        .filter(|word| !word.trim().is_empty())
+        .map(|word| match word.trim().parse() {
+            Ok(word) => word,
+            Err(_) => panic!("Invalid word {:?}", word.trim()),
+        })
+        .collect()
+}
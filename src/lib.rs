@@ -1,2 +1,5 @@
+// Lets `intcode_fn!` expansions refer to `::aoc2019` from inside this crate
+extern crate self as aoc2019;
+
 pub mod intcode;
 pub mod parse;
\ No newline at end of file